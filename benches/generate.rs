@@ -0,0 +1,131 @@
+/*
+ * cargo bench
+ *
+ * Time the whole run of the binary, from reading the config to writing the
+ * filters, on a synthetic config of 2k folders and 50k localparts. Then time
+ * check on two folders sharing 20k localparts, one warning each.
+ */
+
+use std::{
+    env, fs,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+const GROUPS: usize = 40;
+const FOLDERS: usize = 50; // Per group.
+const LOCALPARTS: usize = 25; // Per folder.
+const SHARED: usize = 20_000;
+const RUNS: u32 = 5;
+
+/*
+ * Mostly short form folders, every 10th one is a full form with labels and
+ * every 50th one also has wildcard localparts.
+ */
+fn synthetic_config() -> String {
+    let mut groups = vec![];
+    for g in 0..GROUPS {
+        let mut folders = vec![];
+        for f in 0..FOLDERS {
+            let localparts = (0..LOCALPARTS)
+                .map(|l| format!("\"alias-{}-{}-{}\"", g, f, l))
+                .collect::<Vec<_>>();
+            let folder = if f % 50 == 0 {
+                format!(
+                    r#"{{ "localparts": [{}, "wild-{}-{}-*"], "match": "matches", "labels": {{ "Important": "urgent" }} }}"#,
+                    localparts.join(","),
+                    g,
+                    f
+                )
+            } else if f % 10 == 0 {
+                format!(
+                    r#"{{ "localparts": [{}], "labels": {{ "Bills": ["invoice", "receipt"], "Ads": "sale" }}, "options": {{ "mark-as-read": true }} }}"#,
+                    localparts.join(",")
+                )
+            } else {
+                format!("[{}]", localparts.join(","))
+            };
+            folders.push(format!("\"Folder {}\": {}", f, folder));
+        }
+        groups.push(format!("\"Group {}\": {{ {} }}", g, folders.join(",\n")));
+    }
+    format!(
+        "{{ \"domain.com\": {{ \"options\": {{ \"auto-fullpath\": true }},\n{} }} }}",
+        groups.join(",\n")
+    )
+}
+
+/*
+ * Every localpart of B is already claimed by A.
+ */
+fn warnings_config() -> String {
+    let localparts = (0..SHARED)
+        .map(|l| format!("\n        \"alias-{}\"", l))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        "{{ \"domain.com\": {{\n    \"A\": [{}],\n    \"B\": [{}]\n}} }}",
+        localparts, localparts
+    )
+}
+
+/*
+ * Median and min of RUNS runs of the binary with args, which must exit with code.
+ */
+fn run(args: &[&std::ffi::OsStr], code: i32) -> (Duration, Duration, Duration) {
+    let mut times = vec![];
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let status = Command::new(env!("CARGO_BIN_EXE_sieve-generator"))
+            .args(args)
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        times.push(start.elapsed());
+        assert_eq!(status.code(), Some(code));
+    }
+    times.sort();
+    (times[times.len() / 2], times[0], times.iter().sum())
+}
+
+fn main() {
+    let dir = env::temp_dir().join(format!("sieve-generator-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("sieve.config.json");
+    let output = dir.join("filter.sieve");
+    let prefix = dir.join("prefix.sieve");
+    fs::write(&config, synthetic_config()).unwrap();
+
+    let (median, min, total) = run(
+        &[
+            "--config".as_ref(),
+            config.as_os_str(),
+            "--prefix".as_ref(),
+            prefix.as_os_str(),
+            "--output".as_ref(),
+            output.as_os_str(),
+        ],
+        0,
+    );
+    println!(
+        "generate {} folders, {} localparts: median {:?}, min {:?}, output {} bytes",
+        GROUPS * FOLDERS,
+        GROUPS * FOLDERS * LOCALPARTS,
+        median,
+        min,
+        fs::metadata(&output).unwrap().len()
+    );
+    println!("total {:?} for {} runs", total, RUNS);
+
+    fs::write(&config, warnings_config()).unwrap();
+    let (median, min, total) = run(
+        &["--config".as_ref(), config.as_os_str(), "check".as_ref()],
+        1,
+    );
+    println!(
+        "check {} localparts claimed twice: median {:?}, min {:?}",
+        SHARED, median, min
+    );
+    println!("total {:?} for {} runs", total, RUNS);
+    fs::remove_dir_all(&dir).unwrap();
+}
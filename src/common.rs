@@ -1,28 +1,218 @@
-use regex::Regex;
+use crate::{
+    error::{ErrorKind, Severity},
+    target::Target,
+};
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use std::{collections::BTreeMap, iter::FromIterator};
 
-#[derive(Debug, Deserialize, Serialize)]
+/*
+ * The domain "options" object, read by hand in prepare to report each bad option.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct DomainOptions {
+    pub domain_as_first_folder: bool,
+    pub subdomain_as_first_folder: bool, // Only for *.domain.com, after the domain one if both.
+    pub duplicate_localparts: Severity,  // Same localpart listed in different folders.
+    pub auto_fullpath: bool, // Use fullpath for folders whose generic localparts collide.
+    pub target: Option<Target>,
+    pub priority: i64, // Domains with a higher priority come first, then in config order.
+    pub lists_folder: Option<String>, // Parent of a folder per List-Id label.
+    pub recipients: Recipients, // Also tested by the domain test.
+    pub unknown: CatchAll,
+    pub unknown_mark_as_read: bool,
+    pub unknown_by_localpart: bool, // Unknown/${localpart} instead of Unknown.
+}
+
+impl Default for DomainOptions {
+    fn default() -> Self {
+        DomainOptions {
+            domain_as_first_folder: false,
+            subdomain_as_first_folder: false,
+            duplicate_localparts: Severity::Warning,
+            auto_fullpath: false,
+            target: None,
+            priority: 0,
+            lists_folder: None,
+            recipients: Recipients::default(),
+            unknown: CatchAll::default(),
+            unknown_mark_as_read: true,
+            unknown_by_localpart: false,
+        }
+    }
+}
+
+/*
+ * What happens to the mail no filter takes, the "unknown" domain option.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum CatchAll {
+    Keep,
+    FileInto(String),
+    Reject(String), // With this message.
+    Discard,
+    Redirect(String), // To this address.
+}
+
+impl Default for CatchAll {
+    fn default() -> Self {
+        CatchAll::FileInto(UNKNOWN.to_string())
+    }
+}
+
+impl CatchAll {
+    pub fn folder(&self) -> Option<&str> {
+        match self {
+            CatchAll::FileInto(folder) => Some(folder),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum SieveDomainConfig {
     SimpleFilter(StringOrVec),
     FullFilter(FullFilter),
-    SubDomainConfig(HashMap<String, SieveDomainConfig>),
+    SubDomainConfig(IndexMap<String, SieveDomainConfig>), // In config order.
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct FullFilter<T = StringOrVec, O = Option<FilterOptions>> {
     pub localparts: T,
+    pub senders: Option<T>, // From addresses, or domains without @, whatever the localpart.
+    pub lists: Option<T>,   // Looked for in the List-Id header.
+    #[serde(rename = "match")]
+    pub match_type: Option<MatchType>, // None is literal localparts.
+    pub part: Option<Part>, // None is the whole localpart.
+    pub priority: Option<i64>, // Overrides the order of the filters, higher first.
     pub labels: Option<BTreeMap<String, T>>,
     pub options: O,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq)]
 pub struct FilterOptions<B = Option<bool>> {
     pub generic: B,  // Generate generic filter.
     pub fullpath: B, // If generic, use full path (w parent prefix) in localpart.
-    #[serde(alias = "mark-as-read")]
+    #[serde(rename = "mark-as-read", alias = "mark_as_read")]
     pub mark_as_read: B,
+    pub subaddress: B, // If generic, also name+detail.
+    #[serde(rename = "detail-folder")]
+    pub detail_folder: B, // Into a sub-folder named after the +detail, if any.
+    pub recipients: Option<Recipients>, // None is the domain's.
+}
+
+/*
+ * Where the recipient address is looked for, envelope is the envelope "to",
+ * the others are headers tested with address.
+ */
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Recipient {
+    Envelope,
+    To,
+    Cc,
+    DeliveredTo,
+    XOriginalTo,
+}
+
+impl Recipient {
+    pub const ALL: [Recipient; 5] = [
+        Recipient::Envelope,
+        Recipient::To,
+        Recipient::Cc,
+        Recipient::DeliveredTo,
+        Recipient::XOriginalTo,
+    ];
+
+    /*
+     * The header the address is in, "to" for the envelope of targets without it.
+     */
+    pub fn header(&self) -> &'static str {
+        match self {
+            Recipient::Envelope | Recipient::To => "to",
+            Recipient::Cc => "cc",
+            Recipient::DeliveredTo => "delivered-to",
+            Recipient::XOriginalTo => "x-original-to",
+        }
+    }
+}
+
+/*
+ * A set of recipients, always tested in the order of Recipient::ALL whatever
+ * the order of the config. Only the envelope by default.
+ */
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(from = "Vec<Recipient>", into = "Vec<Recipient>")]
+pub struct Recipients(u8);
+
+impl Recipients {
+    pub fn contains(&self, recipient: Recipient) -> bool {
+        self.0 & 1 << recipient as u8 != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Recipient> + '_ {
+        Recipient::ALL
+            .iter()
+            .copied()
+            .filter(move |&recipient| self.contains(recipient))
+    }
+}
+
+impl Default for Recipients {
+    fn default() -> Self {
+        Recipients::from_iter([Recipient::Envelope])
+    }
+}
+
+impl FromIterator<Recipient> for Recipients {
+    fn from_iter<I: IntoIterator<Item = Recipient>>(recipients: I) -> Self {
+        Recipients(
+            recipients
+                .into_iter()
+                .fold(0, |bits, recipient| bits | 1 << recipient as u8),
+        )
+    }
+}
+
+impl From<Vec<Recipient>> for Recipients {
+    fn from(recipients: Vec<Recipient>) -> Self {
+        recipients.into_iter().collect()
+    }
+}
+
+impl From<Recipients> for Vec<Recipient> {
+    fn from(recipients: Recipients) -> Self {
+        recipients.iter().collect()
+    }
+}
+
+/*
+ * How localparts are compared, by default they are literal and
+ * emitted with :matches with their wildcards escaped.
+ */
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchType {
+    Is,
+    Matches,
+    Regex,
+}
+
+/*
+ * What part of the localpart is compared, with subaddresses (RFC 5233)
+ * "user" is shop in shop+amazon and "detail" is amazon.
+ */
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Part {
+    Localpart,
+    User,
+    Detail,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -32,8 +222,18 @@ pub enum StringOrVec {
     Vec(Vec<String>),
 }
 
-pub fn code_block<T: AsRef<str>>(s: T) -> String {
-    indentasy::indent(s, 1, 4)
+/*
+ * Literal text for :matches, where '*', '?' and '\' are special.
+ */
+pub fn escape_glob(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '*' | '?' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
 }
 
 impl From<StringOrVec> for Vec<String> {
@@ -46,28 +246,23 @@ impl From<StringOrVec> for Vec<String> {
 }
 
 impl StringOrVec {
-    pub fn panic_on_empty(self, variable_name: &str) -> Self {
+    pub fn error_on_empty(self, variable_name: &'static str) -> Result<Self, ErrorKind> {
         match &self {
             StringOrVec::String(string) => {
                 if string.is_empty() {
-                    panic!("ERROR: {} cannot be empty string.", variable_name);
+                    return Err(ErrorKind::EmptyString(variable_name));
                 }
             }
             StringOrVec::Vec(vec) => {
                 if vec.is_empty() {
-                    panic!("ERROR: Array of {} cannot be empty.", variable_name);
+                    return Err(ErrorKind::EmptyArray(variable_name));
                 }
-                for string in vec {
-                    if string.is_empty() {
-                        panic!(
-                            "ERROR: Array of {} cannot contain empty string.",
-                            variable_name
-                        );
-                    }
+                if vec.iter().any(String::is_empty) {
+                    return Err(ErrorKind::EmptyStringInArray(variable_name));
                 }
             }
         }
-        self
+        Ok(self)
     }
 }
 
@@ -83,34 +278,73 @@ impl FilterOptions {
         if let Some(v) = self.mark_as_read {
             new.mark_as_read = v;
         }
+        if let Some(v) = self.subaddress {
+            new.subaddress = v;
+        }
+        if let Some(v) = self.detail_folder {
+            new.detail_folder = v;
+        }
+        if self.recipients.is_some() {
+            new.recipients = self.recipients;
+        }
         new
     }
 }
 
-pub fn is_unknown(path: &str) -> bool {
-    Regex::new(r"^Unknown").unwrap().is_match(path)
+pub const UNKNOWN: &str = "Unknown";
+
+/*
+ * The variable the subdomain of a *.domain.com domain is kept in.
+ */
+pub const SUBDOMAIN: &str = "subdomain";
+
+/*
+ * *.domain.com, any subdomain of domain.com, tested with :matches.
+ */
+pub fn is_wildcard(domain: &str) -> bool {
+    domain.starts_with("*.")
+}
+
+/*
+ * The folder or one of its sub-folders.
+ */
+pub fn is_under(path: &str, folder: &str) -> bool {
+    path.strip_prefix(folder)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::common::FilterOptions;
+    use crate::{common::FilterOptions, error::ErrorKind};
 
     #[test]
-    #[should_panic(expected = "cannot be empty")]
-    fn panic_on_empty_string() {
-        super::StringOrVec::String("".to_string()).panic_on_empty("test");
+    fn error_on_empty_string() {
+        assert_eq!(
+            super::StringOrVec::String("".to_string())
+                .error_on_empty("test")
+                .unwrap_err(),
+            ErrorKind::EmptyString("test")
+        );
     }
 
     #[test]
-    #[should_panic(expected = "cannot be empty")]
-    fn panic_on_empty_array() {
-        super::StringOrVec::Vec(vec![]).panic_on_empty("test");
+    fn error_on_empty_array() {
+        assert_eq!(
+            super::StringOrVec::Vec(vec![])
+                .error_on_empty("test")
+                .unwrap_err(),
+            ErrorKind::EmptyArray("test")
+        );
     }
 
     #[test]
-    #[should_panic(expected = "cannot contain empty")]
-    fn panic_on_array_empty_string() {
-        super::StringOrVec::Vec(vec!["".to_string()]).panic_on_empty("test");
+    fn error_on_array_empty_string() {
+        assert_eq!(
+            super::StringOrVec::Vec(vec!["".to_string()])
+                .error_on_empty("test")
+                .unwrap_err(),
+            ErrorKind::EmptyStringInArray("test")
+        );
     }
 
     #[test]
@@ -123,22 +357,55 @@ mod tests {
 
     #[test]
     fn unwrap_or_default() {
+        let to = Some([super::Recipient::To].iter().copied().collect());
         assert_eq!(
             FilterOptions {
                 generic: true,
                 fullpath: true,
-                mark_as_read: false
+                mark_as_read: false,
+                subaddress: true,
+                detail_folder: false,
+                recipients: to
             },
             FilterOptions {
                 generic: None,
                 fullpath: Some(true),
-                mark_as_read: Some(false)
+                mark_as_read: Some(false),
+                subaddress: Some(true),
+                detail_folder: None,
+                recipients: to
             }
             .unwrap_or_default(FilterOptions {
                 generic: true,
                 fullpath: false,
-                mark_as_read: true
+                mark_as_read: true,
+                subaddress: false,
+                detail_folder: false,
+                recipients: Some(Default::default())
             })
         );
     }
+
+    #[test]
+    fn recipients() {
+        let recipients = serde_json::from_str::<super::Recipients>(
+            r#"["x-original-to", "envelope", "cc", "envelope"]"#,
+        )
+        .unwrap();
+        assert!(recipients.contains(super::Recipient::Cc));
+        assert!(!recipients.contains(super::Recipient::To));
+        assert_eq!(
+            serde_json::to_string(&recipients).unwrap(),
+            r#"["envelope","cc","x-original-to"]"#
+        );
+        assert_eq!(
+            serde_json::to_string(&super::Recipients::default()).unwrap(),
+            r#"["envelope"]"#
+        );
+    }
+
+    #[test]
+    fn escape_glob() {
+        assert_eq!(super::escape_glob(r"a*b?c\d"), r"a\*b\?c\\d");
+    }
 }
@@ -0,0 +1,1615 @@
+use crate::{
+    common::{
+        is_wildcard, CatchAll, DomainOptions, FilterOptions, FullFilter, MatchType, Part,
+        Recipient, Recipients, SieveDomainConfig, StringOrVec,
+    },
+    error::{Error, ErrorKind, GenerateError, Severity},
+    generate,
+    source::{offset_of, Source, Span},
+    target::Target,
+    Options,
+};
+use clap::ValueEnum;
+use indexmap::IndexMap;
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use std::{collections::BTreeMap, fmt, path::Path};
+
+/*
+ * Domains in config order. Parsing goes on past what is wrong in a domain, what
+ * had to be dropped is in diagnostics, which generate reports along with its own.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub domains: Vec<Domain>,
+    pub diagnostics: Vec<GenerateError>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Domain {
+    pub name: String,
+    pub options: DomainOptions,
+    pub config: SieveDomainConfig,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config::default()
+    }
+
+    /*
+     * Only fails if the text can't be read as format at all.
+     */
+    pub fn parse(text: &str, format: Format) -> Result<Self, Error> {
+        Config::from_source(&mut Source::new("", text.to_string()), format)
+    }
+
+    /*
+     * Same as parse, and index the source so that diagnostics can point at it.
+     */
+    pub fn from_source(source: &mut Source, format: Format) -> Result<Self, Error> {
+        let mut sieve_config = parse_sieve_config(&source.text, format).map_err(|e| Error {
+            diagnostics: vec![e],
+        })?;
+        source.index(&serde_json::to_value(&sieve_config).unwrap());
+        let mut config = Config::new();
+        for (name, sieve_domain_config) in sieve_config.iter_mut() {
+            if let Some((sieve_domain_config, options)) =
+                prepare(name, sieve_domain_config, &mut config.diagnostics)
+            {
+                config.domains.push(Domain {
+                    name: name.clone(),
+                    options,
+                    config: sieve_domain_config,
+                });
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn domain(mut self, domain: Domain) -> Self {
+        self.domains.push(domain);
+        self
+    }
+
+    /*
+     * Same checks and lints as generate with these options, the script is dropped.
+     * Ok only has warnings.
+     */
+    pub fn validate(&self, options: &Options) -> Result<Vec<GenerateError>, Error> {
+        generate(self, options).map(|script| script.warnings)
+    }
+}
+
+impl Domain {
+    pub fn new(name: &str) -> Self {
+        Domain {
+            name: name.to_string(),
+            options: DomainOptions::default(),
+            config: SieveDomainConfig::SubDomainConfig(IndexMap::new()),
+        }
+    }
+
+    pub fn options(mut self, options: DomainOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /*
+     * Folders are kept in the order they are added.
+     * Domain::new("domain.com").folder("Newsletter", |f| f.child("Software", ["google"]))
+     * A config set to a filter becomes the self of the folders, which is reported
+     * like in a config file instead of being dropped.
+     */
+    pub fn folder(mut self, name: &str, build: impl FnOnce(Folder) -> Folder) -> Self {
+        if !matches!(self.config, SieveDomainConfig::SubDomainConfig(_)) {
+            let filter = std::mem::replace(
+                &mut self.config,
+                SieveDomainConfig::SubDomainConfig(IndexMap::new()),
+            );
+            self = self.folder_config("self", filter);
+        }
+        self.folder_config(name, build(Folder::default()).into())
+    }
+
+    fn folder_config(mut self, name: &str, config: SieveDomainConfig) -> Self {
+        if let SieveDomainConfig::SubDomainConfig(folders) = &mut self.config {
+            folders.insert(name.to_string(), config);
+        }
+        self
+    }
+
+    /*
+     * The checks and lints of generate for this domain alone, the same ones
+     * as for a parsed config.
+     */
+    pub fn validate(&self, options: &Options) -> Result<Vec<GenerateError>, Error> {
+        Config::new().domain(self.clone()).validate(options)
+    }
+}
+
+/*
+ * A folder built in code, its own filter and its sub-folders. What isn't set is
+ * left out of the config, as if it wasn't written in the file.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Folder {
+    localparts: Vec<String>,
+    senders: Vec<String>,
+    lists: Vec<String>,
+    match_type: Option<MatchType>,
+    part: Option<Part>,
+    priority: Option<i64>,
+    labels: BTreeMap<String, Vec<String>>,
+    options: FilterOptions,
+    children: IndexMap<String, Folder>,
+}
+
+impl Folder {
+    pub fn localparts<S: Into<String>>(mut self, localparts: impl IntoIterator<Item = S>) -> Self {
+        self.localparts
+            .extend(localparts.into_iter().map(Into::into));
+        self
+    }
+
+    /*
+     * From addresses, or whole domains without @.
+     */
+    pub fn senders<S: Into<String>>(mut self, senders: impl IntoIterator<Item = S>) -> Self {
+        self.senders.extend(senders.into_iter().map(Into::into));
+        self
+    }
+
+    /*
+     * Looked for in the List-Id header, e.g. "rust-users.lists.rust-lang.org".
+     */
+    pub fn lists<S: Into<String>>(mut self, lists: impl IntoIterator<Item = S>) -> Self {
+        self.lists.extend(lists.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn match_type(mut self, match_type: MatchType) -> Self {
+        self.match_type = Some(match_type);
+        self
+    }
+
+    pub fn part(mut self, part: Part) -> Self {
+        self.part = Some(part);
+        self
+    }
+
+    pub fn priority(mut self, priority: i64) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    pub fn label<S: Into<String>>(
+        mut self,
+        label: &str,
+        keywords: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.labels
+            .entry(label.to_string())
+            .or_default()
+            .extend(keywords.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn generic(mut self, generic: bool) -> Self {
+        self.options.generic = Some(generic);
+        self
+    }
+
+    pub fn fullpath(mut self, fullpath: bool) -> Self {
+        self.options.fullpath = Some(fullpath);
+        self
+    }
+
+    pub fn mark_as_read(mut self, mark_as_read: bool) -> Self {
+        self.options.mark_as_read = Some(mark_as_read);
+        self
+    }
+
+    pub fn subaddress(mut self, subaddress: bool) -> Self {
+        self.options.subaddress = Some(subaddress);
+        self
+    }
+
+    pub fn detail_folder(mut self, detail_folder: bool) -> Self {
+        self.options.detail_folder = Some(detail_folder);
+        self
+    }
+
+    /*
+     * Replace the domain's for this folder, the domain test keeps using the domain's.
+     */
+    pub fn recipients(mut self, recipients: impl IntoIterator<Item = Recipient>) -> Self {
+        self.options.recipients = Some(recipients.into_iter().collect());
+        self
+    }
+
+    /*
+     * Sub-folder with only localparts.
+     */
+    pub fn child<S: Into<String>>(
+        self,
+        name: &str,
+        localparts: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.folder(name, |f| f.localparts(localparts))
+    }
+
+    pub fn folder(mut self, name: &str, build: impl FnOnce(Folder) -> Folder) -> Self {
+        self.children
+            .insert(name.to_string(), build(Folder::default()));
+        self
+    }
+}
+
+/*
+ * The shortest form that says it all, like it would be written by hand: localparts
+ * alone are a short form filter, a folder with sub-folders puts its own filter in self.
+ */
+impl From<Folder> for SieveDomainConfig {
+    fn from(folder: Folder) -> Self {
+        let Folder {
+            localparts,
+            senders,
+            lists,
+            match_type,
+            part,
+            priority,
+            labels,
+            options,
+            children,
+        } = folder;
+        let has_localparts = !localparts.is_empty();
+        let string_or_vec = |mut values: Vec<String>| match values.len() {
+            0 => StringOrVec::String(String::new()),
+            1 => StringOrVec::String(values.remove(0)),
+            _ => StringOrVec::Vec(values),
+        };
+        let has_senders = !senders.is_empty();
+        let has_lists = !lists.is_empty();
+        let localparts = string_or_vec(localparts);
+        let short = !has_senders
+            && !has_lists
+            && match_type.is_none()
+            && part.is_none()
+            && priority.is_none()
+            && labels.is_empty()
+            && options == FilterOptions::default();
+        let filter = if short {
+            SieveDomainConfig::SimpleFilter(localparts)
+        } else {
+            SieveDomainConfig::FullFilter(FullFilter {
+                localparts,
+                senders: has_senders.then(|| string_or_vec(senders)),
+                lists: has_lists.then(|| string_or_vec(lists)),
+                match_type,
+                part,
+                priority,
+                labels: (!labels.is_empty()).then(|| {
+                    labels
+                        .into_iter()
+                        .map(|(label, keywords)| (label, StringOrVec::Vec(keywords)))
+                        .collect()
+                }),
+                options: (options != FilterOptions::default()).then_some(options),
+            })
+        };
+        if children.is_empty() {
+            return filter;
+        }
+        let mut folders = IndexMap::new();
+        if !short || has_localparts {
+            folders.insert("self".to_string(), filter);
+        }
+        for (name, child) in children {
+            folders.insert(name, child.into());
+        }
+        SieveDomainConfig::SubDomainConfig(folders)
+    }
+}
+
+/*
+ * Back to the config format, { "domain.com": { "options": {..}, "Folder": .. } }.
+ * Options left to their default and unset fields are left out.
+ */
+impl Serialize for Config {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.domains.len()))?;
+        for domain in &self.domains {
+            map.serialize_entry(&domain.name, domain)?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for Domain {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let default = DomainOptions::default();
+        let mut options = serde_json::Map::new();
+        if self.options.domain_as_first_folder != default.domain_as_first_folder {
+            options.insert(
+                "domain-as-first-folder".into(),
+                self.options.domain_as_first_folder.into(),
+            );
+        }
+        if self.options.subdomain_as_first_folder != default.subdomain_as_first_folder {
+            options.insert(
+                "subdomain-as-first-folder".into(),
+                self.options.subdomain_as_first_folder.into(),
+            );
+        }
+        if self.options.duplicate_localparts != default.duplicate_localparts {
+            options.insert(
+                "duplicate-localparts".into(),
+                self.options.duplicate_localparts.to_string().into(),
+            );
+        }
+        if self.options.auto_fullpath != default.auto_fullpath {
+            options.insert("auto-fullpath".into(), self.options.auto_fullpath.into());
+        }
+        if let Some(target) = self.options.target {
+            options.insert(
+                "target".into(),
+                target.to_possible_value().unwrap().get_name().into(),
+            );
+        }
+        if self.options.priority != default.priority {
+            options.insert("priority".into(), self.options.priority.into());
+        }
+        if self.options.recipients != default.recipients {
+            options.insert(
+                "recipients".into(),
+                serde_json::to_value(self.options.recipients).map_err(serde::ser::Error::custom)?,
+            );
+        }
+        if self.options.unknown != default.unknown {
+            options.insert("unknown".into(), catch_all_value(&self.options.unknown));
+        }
+        if self.options.unknown_mark_as_read != default.unknown_mark_as_read {
+            options.insert(
+                "unknown-mark-as-read".into(),
+                self.options.unknown_mark_as_read.into(),
+            );
+        }
+        if self.options.unknown_by_localpart != default.unknown_by_localpart {
+            options.insert(
+                "unknown-by-localpart".into(),
+                self.options.unknown_by_localpart.into(),
+            );
+        }
+        if let Some(lists_folder) = &self.options.lists_folder {
+            options.insert("lists-folder".into(), lists_folder.as_str().into());
+        }
+
+        let mut map = serializer.serialize_map(None)?;
+        if !options.is_empty() {
+            map.serialize_entry("options", &options)?;
+        }
+        match without_nulls(serde_json::to_value(&self.config).map_err(serde::ser::Error::custom)?)
+        {
+            serde_json::Value::Object(folders) => {
+                for (name, folder) in folders {
+                    map.serialize_entry(&name, &folder)?;
+                }
+            }
+            _ => return Err(serde::ser::Error::custom("a domain must be an object")),
+        }
+        map.end()
+    }
+}
+
+fn without_nulls(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(object) => object
+            .into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| (key, without_nulls(value)))
+            .collect(),
+        value => value,
+    }
+}
+
+/*
+ * How the config file is written.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Format {
+    Json,
+    /*
+     * JSON with comments and trailing commas, also accepts JSONC.
+     */
+    #[value(alias = "jsonc")]
+    Json5,
+    #[value(alias = "yml")]
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    /*
+     * .json5/.jsonc -> Json5, .yaml/.yml -> Yaml, .toml -> Toml, anything else -> Json
+     */
+    pub fn from_path(file_path: &str) -> Self {
+        match Path::new(file_path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase())
+            .as_deref()
+        {
+            Some("json5") | Some("jsonc") => Format::Json5,
+            Some("yaml") | Some("yml") => Format::Yaml,
+            Some("toml") => Format::Toml,
+            _ => Format::Json,
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Format::Json => "JSON",
+            Format::Json5 => "JSON5",
+            Format::Yaml => "YAML",
+            Format::Toml => "TOML",
+        })
+    }
+}
+
+/*
+ * Read as type SieveConfig to avoid having to include the options in the type
+ * since there's only one and it will be removed from the object anyway.
+ *
+ * Enforce config json file structure.
+ * {
+ *     [domain: string]: {
+ *          options,
+ *         [dirname: string]: JSON
+ *    }
+ * }
+ */
+type SieveConfig = IndexMap<String, IndexMap<String, serde_json::Value>>; // In config order.
+
+/*
+ * Every format is read into the same JSON values so the untagged SieveDomainConfig
+ * variants and the domain options are resolved the same way in prepare.
+ * json5 is a superset of JSON, it takes care of comments and trailing commas.
+ */
+#[allow(clippy::result_large_err)] // Only built once, on failure.
+fn parse_sieve_config(content: &str, format: Format) -> Result<SieveConfig, GenerateError> {
+    let at = |line: usize, column: usize| {
+        let start = offset_of(content, line, column);
+        Some(Span { start, end: start })
+    };
+    let (message, span) = match format {
+        Format::Json => match serde_json::from_str(content) {
+            Ok(sieve_config) => return Ok(sieve_config),
+            Err(e) => (e.to_string(), at(e.line(), e.column())),
+        },
+        Format::Json5 => match json5::from_str(content) {
+            Ok(sieve_config) => return Ok(sieve_config),
+            Err(json5::Error::Message { msg, location }) => (
+                msg,
+                location.and_then(|location| at(location.line, location.column)),
+            ),
+        },
+        Format::Yaml => match serde_yaml::from_str(content) {
+            Ok(sieve_config) => return Ok(sieve_config),
+            Err(e) => (
+                e.to_string(),
+                e.location().map(|location| Span {
+                    start: location.index(),
+                    end: location.index(),
+                }),
+            ),
+        },
+        Format::Toml => match toml::from_str::<SieveConfig>(content) {
+            Ok(sieve_config) => return Ok(sieve_config),
+            Err(e) => (
+                e.message().to_string(),
+                e.span().map(|span| Span {
+                    start: span.start,
+                    end: span.end,
+                }),
+            ),
+        },
+    };
+    let mut error =
+        ErrorKind::ParseConfig(format!("invalid {}: {}", format, message)).at::<&str>("", &[]);
+    error.span = span;
+    Err(error)
+}
+
+/*
+ * Take out the options object and return the domain config object.
+ * Errors and warnings are pushed to diagnostics, what is wrong is dropped
+ * so that the rest can still be generated and checked.
+ */
+fn prepare(
+    domain: &str,
+    sieve_domain_config: &mut IndexMap<String, serde_json::Value>,
+    diagnostics: &mut Vec<GenerateError>,
+) -> Option<(SieveDomainConfig, DomainOptions)> {
+    let mut domain_options = DomainOptions::default();
+    if let Some(options) = sieve_domain_config.shift_remove("options") {
+        if let serde_json::Value::Object(options) = options {
+            for (key, value) in options {
+                let path = ["options", &key];
+                match key.as_str() {
+                    "domain-as-first-folder" => match value.as_bool() {
+                        Some(value) => domain_options.domain_as_first_folder = value,
+                        None => diagnostics
+                            .push(ErrorKind::OptionNotBool(key.clone()).at(domain, &path)),
+                    },
+                    "subdomain-as-first-folder" => match value.as_bool() {
+                        Some(true) if !is_wildcard(domain) => diagnostics.push(
+                            ErrorKind::InvalidOptionValue(
+                                key.clone(),
+                                "false unless the domain is *.domain",
+                            )
+                            .at(domain, &path),
+                        ),
+                        Some(value) => domain_options.subdomain_as_first_folder = value,
+                        None => diagnostics
+                            .push(ErrorKind::OptionNotBool(key.clone()).at(domain, &path)),
+                    },
+                    "auto-fullpath" => match value.as_bool() {
+                        Some(value) => domain_options.auto_fullpath = value,
+                        None => diagnostics
+                            .push(ErrorKind::OptionNotBool(key.clone()).at(domain, &path)),
+                    },
+                    "unknown" => match catch_all(&value) {
+                        Some(unknown) => domain_options.unknown = unknown,
+                        None => diagnostics.push(
+                            ErrorKind::InvalidOptionValue(key.clone(), CATCH_ALL).at(domain, &path),
+                        ),
+                    },
+                    "unknown-mark-as-read" => match value.as_bool() {
+                        Some(value) => domain_options.unknown_mark_as_read = value,
+                        None => diagnostics
+                            .push(ErrorKind::OptionNotBool(key.clone()).at(domain, &path)),
+                    },
+                    "unknown-by-localpart" => match value.as_bool() {
+                        Some(value) => domain_options.unknown_by_localpart = value,
+                        None => diagnostics
+                            .push(ErrorKind::OptionNotBool(key.clone()).at(domain, &path)),
+                    },
+                    "target" => match value.as_str().and_then(|v| Target::from_str(v, true).ok()) {
+                        Some(target) => domain_options.target = Some(target),
+                        None => diagnostics.push(
+                            ErrorKind::InvalidOptionValue(
+                                key.clone(),
+                                "\"pigeonhole\", \"proton\", \"fastmail\" or \"stalwart\"",
+                            )
+                            .at(domain, &path),
+                        ),
+                    },
+                    "priority" => match value.as_i64() {
+                        Some(priority) => domain_options.priority = priority,
+                        None => diagnostics.push(
+                            ErrorKind::InvalidOptionValue(key.clone(), "an integer")
+                                .at(domain, &path),
+                        ),
+                    },
+                    "recipients" => match recipients(&value) {
+                        Some(recipients) => domain_options.recipients = recipients,
+                        None => diagnostics.push(
+                            ErrorKind::InvalidOptionValue(key.clone(), RECIPIENTS)
+                                .at(domain, &path),
+                        ),
+                    },
+                    "lists-folder" => match value.as_str().map(|v| v.trim_matches('/')) {
+                        Some(folder) if !folder.is_empty() => {
+                            domain_options.lists_folder = Some(folder.to_string())
+                        }
+                        _ => diagnostics.push(
+                            ErrorKind::InvalidOptionValue(key.clone(), "a folder path")
+                                .at(domain, &path),
+                        ),
+                    },
+                    "duplicate-localparts" => match value.as_str() {
+                        Some("error") => domain_options.duplicate_localparts = Severity::Error,
+                        Some("warning") => domain_options.duplicate_localparts = Severity::Warning,
+                        _ => diagnostics.push(
+                            ErrorKind::InvalidOptionValue(key.clone(), "\"error\" or \"warning\"")
+                                .at(domain, &path),
+                        ),
+                    },
+                    _ => diagnostics.push(ErrorKind::UnknownKey(key.clone()).at(domain, &path)),
+                }
+            }
+        } else {
+            diagnostics.push(ErrorKind::OptionsNotObject.at(domain, &["options"]));
+        }
+        /* A folder per localpart needs a folder to put them in. */
+        if domain_options.unknown_by_localpart && domain_options.unknown.folder().is_none() {
+            diagnostics.push(
+                ErrorKind::InvalidOptionValue(
+                    "unknown-by-localpart".to_string(),
+                    "false unless unknown files into a folder",
+                )
+                .at(domain, &["options", "unknown-by-localpart"]),
+            );
+        }
+    }
+    sieve_domain_config
+        .retain(|sub, value| sanitize(domain, std::slice::from_ref(sub), value, diagnostics));
+    match serde_json::from_value(serde_json::to_value(sieve_domain_config).unwrap()) {
+        Ok(sieve_domain_config) => Some((sieve_domain_config, domain_options)),
+        Err(e) => {
+            diagnostics.push(ErrorKind::InvalidFolderConfig(e.to_string()).at::<&str>(domain, &[]));
+            None
+        }
+    }
+}
+
+const RECIPIENTS: &str =
+    "a non-empty array of \"envelope\", \"to\", \"cc\", \"delivered-to\" or \"x-original-to\"";
+
+const CATCH_ALL: &str =
+    "\"keep\", \"discard\" or an object with one of \"fileinto\", \"reject\" or \"redirect\"";
+
+/*
+ * The unknown domain option, None if it isn't valid.
+ * "keep", "discard", { "fileinto": "Folder" }, { "reject": "Message" } or { "redirect": "a@b.com" }
+ */
+fn catch_all(value: &serde_json::Value) -> Option<CatchAll> {
+    match value {
+        serde_json::Value::String(action) => match action.as_str() {
+            "keep" => Some(CatchAll::Keep),
+            "discard" => Some(CatchAll::Discard),
+            _ => None,
+        },
+        serde_json::Value::Object(action) if action.len() == 1 => {
+            let (name, argument) = action.iter().next()?;
+            let argument = argument.as_str()?;
+            match name.as_str() {
+                "fileinto" => Some(argument.trim_matches('/'))
+                    .filter(|folder| !folder.is_empty())
+                    .map(|folder| CatchAll::FileInto(folder.to_string())),
+                "reject" if !argument.is_empty() => Some(CatchAll::Reject(argument.to_string())),
+                "redirect" if argument.contains('@') => {
+                    Some(CatchAll::Redirect(argument.to_string()))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/*
+ * Back to the form catch_all reads.
+ */
+fn catch_all_value(unknown: &CatchAll) -> serde_json::Value {
+    let action = |name: &str, argument: &str| serde_json::json!({ name: argument });
+    match unknown {
+        CatchAll::Keep => "keep".into(),
+        CatchAll::Discard => "discard".into(),
+        CatchAll::FileInto(folder) => action("fileinto", folder),
+        CatchAll::Reject(message) => action("reject", message),
+        CatchAll::Redirect(address) => action("redirect", address),
+    }
+}
+
+/*
+ * The recipients option of a domain or a folder, None if it isn't valid.
+ */
+fn recipients(value: &serde_json::Value) -> Option<Recipients> {
+    serde_json::from_value::<Vec<Recipient>>(value.clone())
+        .ok()
+        .map(Recipients::from)
+        .filter(|recipients| !recipients.is_empty())
+}
+
+/*
+ * Check the JSON types the untagged SieveDomainConfig expects, so a wrong type is
+ * reported where it is instead of failing the whole domain. Return false if the
+ * value has to be dropped.
+ */
+fn sanitize(
+    domain: &str,
+    config_path: &[String],
+    value: &mut serde_json::Value,
+    diagnostics: &mut Vec<GenerateError>,
+) -> bool {
+    use serde_json::Value;
+    let is_string_or_array = |value: &Value| match value {
+        Value::String(_) => true,
+        Value::Array(array) => array.iter().all(Value::is_string),
+        _ => false,
+    };
+    let path_with = |keys: &[&str]| {
+        config_path
+            .iter()
+            .map(String::as_str)
+            .chain(keys.iter().copied())
+            .map(String::from)
+            .collect::<Vec<_>>()
+    };
+    match value {
+        Value::String(_) | Value::Array(_) => {
+            if !is_string_or_array(value) {
+                diagnostics.push(ErrorKind::NotStringOrArray("localparts").at(domain, config_path));
+                return false;
+            }
+        }
+        Value::Object(full_filter)
+            if ["localparts", "senders", "lists"]
+                .iter()
+                .any(|&key| full_filter.contains_key(key)) =>
+        {
+            for key in ["localparts", "senders", "lists"] {
+                if full_filter
+                    .get(key)
+                    .is_some_and(|value| !is_string_or_array(value))
+                {
+                    diagnostics
+                        .push(ErrorKind::NotStringOrArray(key).at(domain, &path_with(&[key])));
+                    return false;
+                }
+            }
+            /* A folder with only senders or lists, it has no localparts of its own. */
+            full_filter
+                .entry("localparts")
+                .or_insert_with(|| Value::String(String::new()));
+            for key in full_filter.keys() {
+                if ![
+                    "localparts",
+                    "senders",
+                    "lists",
+                    "match",
+                    "part",
+                    "priority",
+                    "labels",
+                    "options",
+                ]
+                .contains(&key.as_str())
+                {
+                    diagnostics
+                        .push(ErrorKind::UnknownKey(key.clone()).at(domain, &path_with(&[key])));
+                }
+            }
+            match full_filter.get("match") {
+                Some(Value::String(match_type))
+                    if ["is", "matches", "regex"].contains(&match_type.as_str()) => {}
+                Some(Value::Null) | None => {}
+                Some(_) => {
+                    diagnostics.push(
+                        ErrorKind::InvalidOptionValue(
+                            "match".to_string(),
+                            "\"is\", \"matches\" or \"regex\"",
+                        )
+                        .at(domain, &path_with(&["match"])),
+                    );
+                    full_filter.remove("match");
+                }
+            }
+            match full_filter.get("part") {
+                Some(Value::String(part))
+                    if ["localpart", "user", "detail"].contains(&part.as_str()) => {}
+                Some(Value::Null) | None => {}
+                Some(_) => {
+                    diagnostics.push(
+                        ErrorKind::InvalidOptionValue(
+                            "part".to_string(),
+                            "\"localpart\", \"user\" or \"detail\"",
+                        )
+                        .at(domain, &path_with(&["part"])),
+                    );
+                    full_filter.remove("part");
+                }
+            }
+            match full_filter.get("priority") {
+                Some(Value::Number(priority)) if priority.is_i64() => {}
+                Some(Value::Null) | None => {}
+                Some(_) => {
+                    diagnostics.push(
+                        ErrorKind::InvalidOptionValue("priority".to_string(), "an integer")
+                            .at(domain, &path_with(&["priority"])),
+                    );
+                    full_filter.remove("priority");
+                }
+            }
+            match full_filter.get_mut("labels") {
+                Some(Value::Object(labels)) => labels.retain(|label, keywords| {
+                    is_string_or_array(keywords) || {
+                        diagnostics.push(
+                            ErrorKind::NotStringOrArray("label keywords")
+                                .at(domain, &path_with(&["labels", label])),
+                        );
+                        false
+                    }
+                }),
+                Some(Value::Null) | None => {}
+                Some(_) => {
+                    diagnostics
+                        .push(ErrorKind::LabelsNotObject.at(domain, &path_with(&["labels"])));
+                    full_filter.remove("labels");
+                }
+            }
+            match full_filter.get_mut("options") {
+                Some(Value::Object(options)) => options.retain(|option, value| {
+                    if option == "recipients" {
+                        value.is_null() || recipients(value).is_some() || {
+                            diagnostics.push(
+                                ErrorKind::InvalidOptionValue(option.clone(), RECIPIENTS)
+                                    .at(domain, &path_with(&["options", option])),
+                            );
+                            false
+                        }
+                    } else if ![
+                        "generic",
+                        "fullpath",
+                        "mark-as-read",
+                        "mark_as_read",
+                        "subaddress",
+                        "detail-folder",
+                    ]
+                    .contains(&option.as_str())
+                    {
+                        diagnostics.push(
+                            ErrorKind::UnknownKey(option.clone())
+                                .at(domain, &path_with(&["options", option])),
+                        );
+                        true
+                    } else if value.is_boolean() || value.is_null() {
+                        true
+                    } else {
+                        diagnostics.push(
+                            ErrorKind::OptionNotBool(option.clone())
+                                .at(domain, &path_with(&["options", option])),
+                        );
+                        false
+                    }
+                }),
+                Some(Value::Null) | None => {}
+                Some(_) => {
+                    diagnostics
+                        .push(ErrorKind::OptionsNotObject.at(domain, &path_with(&["options"])));
+                    full_filter.remove("options");
+                }
+            }
+        }
+        Value::Object(sub_domain_configs) => sub_domain_configs
+            .retain(|sub, value| sanitize(domain, &path_with(&[sub]), value, diagnostics)),
+        _ => {
+            diagnostics.push(ErrorKind::InvalidFolderValue.at(domain, config_path));
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    /*
+     * The commented config example of the README.
+     */
+    fn readme_config_example() -> &'static str {
+        let readme = include_str!("../README.md");
+        let start = readme.find("```json5\n").unwrap() + "```json5\n".len();
+        &readme[start..start + readme[start..].find("```").unwrap()]
+    }
+
+    /*
+     * The script the README documents for that example.
+     */
+    fn readme_script_example() -> &'static str {
+        let readme = include_str!("../README.md");
+        let marker = "The `JSON` above will produce\n\n```\n";
+        let start = readme.find(marker).unwrap() + marker.len();
+        readme[start..start + readme[start..].find("```").unwrap()].trim_end()
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(
+            super::Format::from_path("sieve.config.json"),
+            super::Format::Json
+        );
+        assert_eq!(
+            super::Format::from_path("sieve.config.JSON5"),
+            super::Format::Json5
+        );
+        assert_eq!(
+            super::Format::from_path("sieve.config.jsonc"),
+            super::Format::Json5
+        );
+        assert_eq!(
+            super::Format::from_path("sieve.config"),
+            super::Format::Json
+        );
+    }
+
+    #[test]
+    fn parse_json5_readme_example() {
+        let config =
+            super::parse_sieve_config(readme_config_example(), super::Format::Json5).unwrap();
+        assert_eq!(
+            config,
+            serde_json::from_str::<super::SieveConfig>(
+                r#"
+                {
+                    "domain.com": {
+                        "options": {
+                            "domain-as-first-folder": false,
+                            "duplicate-localparts": "warning",
+                            "auto-fullpath": false
+                        },
+                        "Newsletter": {
+                            "Software": ["google", "facebook"],
+                            "Business": "wallstreet"
+                        },
+                        "Utilities": {
+                            "self": ["electricity"],
+                            "Grocery": {
+                                "localparts": "market",
+                                "labels": {
+                                    "label": "keyword",
+                                    "label2": ["keyword2"]
+                                },
+                                "options": {
+                                    "fullpath": true,
+                                    "mark-as-read": true
+                                }
+                            }
+                        }
+                    }
+                }"#
+            )
+            .unwrap()
+        );
+
+        let config = super::Config::parse(readme_config_example(), super::Format::Json5).unwrap();
+        let script = crate::generate(&config, &crate::Options::default()).unwrap();
+        assert!(script.warnings.is_empty(), "{:?}", script.warnings);
+        assert_eq!(script.to_string(), readme_script_example());
+    }
+
+    /*
+     * Same config as examples/sieve.config.json
+     */
+    #[test]
+    fn parse_yaml_same_as_json() {
+        assert_eq!(
+            super::parse_sieve_config(
+                r#"
+domain.com:
+  options:
+    domain-as-first-folder: false
+  Newsletter:
+    Software: [google, facebook]
+    Business:
+      localparts: wallstreet
+      options:
+        fullpath: true
+  Utilities:
+    self:
+      localparts: [electricity]
+      options:
+        generic: false
+    Grocery:
+      localparts: market
+      labels:
+        label: keyword
+      options:
+        mark-as-read: true
+    Bill:
+      localparts: ""
+      labels:
+        label2: [keyword2]
+        label3: [keyword3]
+      options:
+        mark-as-read: true
+"#,
+                super::Format::Yaml
+            )
+            .unwrap(),
+            super::parse_sieve_config(
+                include_str!("../examples/sieve.config.json"),
+                super::Format::Json
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_toml_same_as_json() {
+        assert_eq!(
+            super::parse_sieve_config(
+                r#"
+["domain.com".options]
+domain-as-first-folder = false
+
+["domain.com".Newsletter]
+Software = ["google", "facebook"]
+Business = { localparts = "wallstreet", options = { fullpath = true } }
+
+["domain.com".Utilities.self]
+localparts = ["electricity"]
+options = { generic = false }
+
+["domain.com".Utilities.Grocery]
+localparts = "market"
+labels = { label = "keyword" }
+options = { mark-as-read = true }
+
+["domain.com".Utilities.Bill]
+localparts = ""
+labels = { label2 = ["keyword2"], label3 = ["keyword3"] }
+options = { mark-as-read = true }
+"#,
+                super::Format::Toml
+            )
+            .unwrap(),
+            super::parse_sieve_config(
+                include_str!("../examples/sieve.config.json"),
+                super::Format::Json
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn prepare_yaml() {
+        let mut config = super::parse_sieve_config(
+            r#"
+domain.com:
+  options:
+    domain-as-first-folder: true
+  Newsletter:
+    self: [news]
+    Business: wallstreet
+"#,
+            super::Format::Yaml,
+        )
+        .unwrap();
+        let (allowlist, domain_options) = super::prepare(
+            "domain.com",
+            config.get_mut("domain.com").unwrap(),
+            &mut vec![],
+        )
+        .unwrap();
+        assert!(domain_options.domain_as_first_folder);
+        assert!(matches!(
+            allowlist,
+            super::SieveDomainConfig::SubDomainConfig(_)
+        ));
+    }
+
+    #[test]
+    fn prepare_collect_all_errors() {
+        let mut errors = vec![];
+        let (allowlist, _) = super::prepare(
+            "domain.com",
+            &mut serde_json::from_str::<super::IndexMap<String, serde_json::Value>>(
+                r#"
+                {
+                    "options": {
+                        "domain-as-first-folder": "yes",
+                        "duplicate-localparts": "fatal",
+                        "priority": "high",
+                        "lists-folder": "/",
+                        "recipients": []
+                    },
+                    "Newsletter": {
+                        "Software": ["google", 1],
+                        "Business": {
+                            "localparts": "wallstreet",
+                            "match": "glob",
+                            "part": "domain",
+                            "priority": 1.5,
+                            "labels": { "label": 1 },
+                            "options": { "generic": "no", "mark-as-read": true, "recipients": ["bcc"] }
+                        },
+                        "Ads": null
+                    },
+                    "Utilities": {
+                        "localparts": "electricity",
+                        "labels": "keyword",
+                        "options": ""
+                    }
+                }"#,
+            )
+            .unwrap(),
+            &mut errors,
+        )
+        .unwrap();
+        let mut errors = errors
+            .into_iter()
+            .map(|e| format!("{} {}", e.code(), e.location()))
+            .collect::<Vec<_>>();
+        errors.sort();
+        assert_eq!(
+            errors,
+            [
+                "E0003 domain.com › Utilities › options",
+                "E0004 domain.com › Newsletter › Business › options › generic",
+                "E0004 domain.com › options › domain-as-first-folder",
+                "E0016 domain.com › Newsletter › Business › labels › label",
+                "E0016 domain.com › Newsletter › Software",
+                "E0017 domain.com › Utilities › labels",
+                "E0018 domain.com › Newsletter › Ads",
+                "E0019 domain.com › Newsletter › Business › match",
+                "E0019 domain.com › Newsletter › Business › options › recipients",
+                "E0019 domain.com › Newsletter › Business › part",
+                "E0019 domain.com › Newsletter › Business › priority",
+                "E0019 domain.com › options › duplicate-localparts",
+                "E0019 domain.com › options › lists-folder",
+                "E0019 domain.com › options › priority",
+                "E0019 domain.com › options › recipients",
+            ]
+        );
+        /* What is left can still be generated. */
+        assert_eq!(
+            serde_json::to_value(&allowlist).unwrap(),
+            serde_json::json!({
+                "Newsletter": {
+                    "Business": {
+                        "localparts": "wallstreet",
+                        "senders": null,
+                        "lists": null,
+                        "match": null,
+                        "part": null,
+                        "priority": null,
+                        "labels": {},
+                        "options": {
+                            "generic": null,
+                            "fullpath": null,
+                            "mark-as-read": true,
+                            "subaddress": null,
+                            "detail-folder": null,
+                            "recipients": null
+                        }
+                    }
+                },
+                "Utilities": {
+                    "localparts": "electricity",
+                    "senders": null,
+                    "lists": null,
+                    "match": null,
+                    "part": null,
+                    "priority": null,
+                    "labels": null,
+                    "options": null
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn prepare_unknown_keys() {
+        let mut diagnostics = vec![];
+        super::prepare(
+            "domain.com",
+            &mut serde_json::from_str::<super::IndexMap<String, serde_json::Value>>(
+                r#"
+                {
+                    "options": { "domain-as-first-folders": true },
+                    "Newsletter": {
+                        "localparts": "news",
+                        "label": { "label": "keyword" },
+                        "options": { "mark-as-reads": true }
+                    }
+                }"#,
+            )
+            .unwrap(),
+            &mut diagnostics,
+        )
+        .unwrap();
+        let mut diagnostics = diagnostics
+            .into_iter()
+            .map(|e| format!("{} {} {}", e.severity, e.code(), e.location()))
+            .collect::<Vec<_>>();
+        diagnostics.sort();
+        assert_eq!(
+            diagnostics,
+            [
+                "warning L0003 domain.com › Newsletter › label",
+                "warning L0003 domain.com › Newsletter › options › mark-as-reads",
+                "warning L0003 domain.com › options › domain-as-first-folders",
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_error_names_format() {
+        let e = super::parse_sieve_config("\ndomain.com = [", super::Format::Toml).unwrap_err();
+        assert!(e.to_string().contains("invalid TOML:"));
+        assert!(e.span.is_some());
+        let e = super::parse_sieve_config("domain.com: [", super::Format::Yaml).unwrap_err();
+        assert!(e.to_string().contains("invalid YAML:"));
+        assert!(e.span.is_some());
+    }
+
+    #[test]
+    fn parse_error_span() {
+        let content = "{\n    \"domain.com\": {\n        \"A\": \"a\",,\n    }\n}";
+        for format in [super::Format::Json, super::Format::Json5] {
+            let e = super::parse_sieve_config(content, format).unwrap_err();
+            assert_eq!(
+                super::Source::new("sieve.config", content.to_string())
+                    .line_col(e.span.unwrap().start),
+                (3, 18),
+                "{}",
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn prepare_error_span() {
+        let content = r#"
+domain.com:
+  options:
+    domain-as-first-folder: "yes"
+"#;
+        let mut source = super::Source::new("sieve.config.yaml", content.to_string());
+        let mut sieve_config = super::parse_sieve_config(content, super::Format::Yaml).unwrap();
+        source.index(&serde_json::to_value(&sieve_config).unwrap());
+        let mut errors = vec![];
+        super::prepare(
+            "domain.com",
+            sieve_config.get_mut("domain.com").unwrap(),
+            &mut errors,
+        );
+        assert_eq!(
+            source.render(&errors[0]),
+            r#"error[E0004]: domain-as-first-folder option must be bool
+ --> sieve.config.yaml:4:5
+  |
+4 |     domain-as-first-folder: "yes"
+  |     ^^^^^^^^^^^^^^^^^^^^^^
+  = note: in domain.com › options › domain-as-first-folder"#
+        );
+    }
+
+    #[test]
+    fn parse_json_rejects_comments() {
+        assert!(serde_json::from_str::<super::SieveConfig>(readme_config_example()).is_err());
+    }
+
+    #[test]
+    fn prepare() {
+        let mut errors = vec![];
+        let (allowlist, domain_options) = super::prepare(
+            "domain.com",
+            &mut serde_json::from_str::<super::IndexMap<String, serde_json::Value>>(
+                r#"
+                {
+                    "options": {
+                        "domain-as-first-folder": true,
+                        "duplicate-localparts": "error",
+                        "target": "Proton"
+                    },
+                    "Newsletter": {
+                        "Business": "wallstreet"
+                    }
+                }"#,
+            )
+            .unwrap(),
+            &mut errors,
+        )
+        .unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(domain_options.duplicate_localparts, super::Severity::Error);
+        assert_eq!(domain_options.target, Some(super::Target::Proton));
+        assert_eq!(
+            serde_json::to_string(&allowlist).unwrap(),
+            "{\"Newsletter\":{\"Business\":\"wallstreet\"}}",
+        );
+        assert!(domain_options.domain_as_first_folder);
+    }
+
+    #[test]
+    fn prepare_error_options_not_object() {
+        let mut errors = vec![];
+        super::prepare(
+            "domain.com",
+            &mut serde_json::from_str::<super::IndexMap<String, serde_json::Value>>(
+                r#"
+                {
+                    "options": ""
+                }"#,
+            )
+            .unwrap(),
+            &mut errors,
+        );
+        let e = &errors[0];
+        assert_eq!(e.code(), "E0003");
+        assert_eq!(e.location(), "domain.com › options");
+    }
+
+    #[test]
+    fn prepare_error_options_first_folder_not_boolean() {
+        let mut errors = vec![];
+        super::prepare(
+            "domain.com",
+            &mut serde_json::from_str::<super::IndexMap<String, serde_json::Value>>(
+                r#"
+                {
+                    "options": {
+                        "domain-as-first-folder": ""
+                    }
+                }"#,
+            )
+            .unwrap(),
+            &mut errors,
+        );
+        let e = &errors[0];
+        assert_eq!(e.code(), "E0004");
+        assert_eq!(
+            e.location(),
+            "domain.com › options › domain-as-first-folder"
+        );
+    }
+
+    #[test]
+    fn prepare_error_options_invalid_target() {
+        let mut errors = vec![];
+        let (_, domain_options) = super::prepare(
+            "domain.com",
+            &mut serde_json::from_str::<super::IndexMap<String, serde_json::Value>>(
+                r#"
+                {
+                    "options": {
+                        "target": "gmail"
+                    }
+                }"#,
+            )
+            .unwrap(),
+            &mut errors,
+        )
+        .unwrap();
+        assert_eq!(domain_options.target, None);
+        let e = &errors[0];
+        assert_eq!(e.code(), "E0019");
+        assert_eq!(e.location(), "domain.com › options › target");
+    }
+
+    #[test]
+    fn prepare_unknown() {
+        let prepare = |options: serde_json::Value| {
+            let mut errors = vec![];
+            let (_, domain_options) = super::prepare(
+                "domain.com",
+                &mut serde_json::from_value(serde_json::json!({ "options": options })).unwrap(),
+                &mut errors,
+            )
+            .unwrap();
+            (
+                domain_options.unknown,
+                errors
+                    .iter()
+                    .map(|e| format!("{} {}", e.code(), e.location()))
+                    .collect::<Vec<_>>(),
+            )
+        };
+        assert_eq!(
+            prepare(serde_json::json!({ "unknown": { "fileinto": "/Spam/" } })),
+            (super::CatchAll::FileInto("Spam".to_string()), vec![])
+        );
+        assert_eq!(
+            prepare(serde_json::json!({ "unknown": "discard" })),
+            (super::CatchAll::Discard, vec![])
+        );
+        for unknown in [
+            serde_json::json!("reject"),
+            serde_json::json!({ "redirect": "nobody" }),
+            serde_json::json!({ "reject": "No.", "redirect": "me@domain.com" }),
+            serde_json::json!({ "fileinto": "/" }),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                prepare(serde_json::json!({ "unknown": unknown })),
+                (
+                    super::CatchAll::default(),
+                    vec!["E0019 domain.com › options › unknown".to_string()]
+                )
+            );
+        }
+        assert_eq!(
+            prepare(serde_json::json!({ "unknown": "keep", "unknown-by-localpart": true })),
+            (
+                super::CatchAll::Keep,
+                vec!["E0019 domain.com › options › unknown-by-localpart".to_string()]
+            )
+        );
+    }
+
+    #[test]
+    fn prepare_senders() {
+        let mut errors = vec![];
+        let (config, _) = super::prepare(
+            "domain.com",
+            &mut serde_json::from_str::<super::IndexMap<String, serde_json::Value>>(
+                r#"
+                {
+                    "GitHub": { "senders": "github.com" },
+                    "Bad": { "senders": 1 }
+                }"#,
+            )
+            .unwrap(),
+            &mut errors,
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(config).unwrap(),
+            serde_json::json!({
+                "GitHub": {
+                    "localparts": "",
+                    "senders": "github.com",
+                    "lists": null,
+                    "match": null,
+                    "part": null,
+                    "priority": null,
+                    "labels": null,
+                    "options": null
+                }
+            })
+        );
+        let e = &errors[0];
+        assert_eq!(e.code(), "E0016");
+        assert_eq!(e.location(), "domain.com › Bad › senders");
+    }
+
+    #[test]
+    fn build_folders() {
+        let domain = super::Domain::new("domain.com")
+            .options(super::DomainOptions {
+                lists_folder: Some("Lists".to_string()),
+                recipients: [super::Recipient::Envelope, super::Recipient::DeliveredTo]
+                    .iter()
+                    .copied()
+                    .collect(),
+                unknown: super::CatchAll::FileInto("Spam".to_string()),
+                unknown_mark_as_read: false,
+                unknown_by_localpart: true,
+                ..Default::default()
+            })
+            .folder("Newsletter", |f| {
+                f.child("Software", ["google", "facebook"])
+                    .child("Business", ["wallstreet"])
+            })
+            .folder("Utilities", |f| {
+                f.localparts(["electricity"]).folder("Grocery", |f| {
+                    f.localparts(["market"])
+                        .label("label", ["keyword"])
+                        .fullpath(true)
+                        .mark_as_read(true)
+                })
+            })
+            .folder("Shop", |f| {
+                f.localparts(["shop*"])
+                    .match_type(super::MatchType::Matches)
+                    .part(super::Part::User)
+                    .priority(1)
+                    .subaddress(true)
+                    .detail_folder(true)
+            })
+            .folder("GitHub", |f| {
+                f.senders(["github.com"]).recipients([super::Recipient::To])
+            })
+            .folder("Rust", |f| f.lists(["rust-users.lists.rust-lang.org"]));
+        assert_eq!(
+            serde_json::to_value(super::Config::new().domain(domain)).unwrap(),
+            serde_json::json!({
+                "domain.com": {
+                    "options": {
+                        "recipients": ["envelope", "delivered-to"],
+                        "unknown": { "fileinto": "Spam" },
+                        "unknown-mark-as-read": false,
+                        "unknown-by-localpart": true,
+                        "lists-folder": "Lists"
+                    },
+                    "Newsletter": {
+                        "Software": ["google", "facebook"],
+                        "Business": "wallstreet"
+                    },
+                    "Utilities": {
+                        "self": "electricity",
+                        "Grocery": {
+                            "localparts": "market",
+                            "labels": { "label": ["keyword"] },
+                            "options": { "fullpath": true, "mark-as-read": true }
+                        }
+                    },
+                    "Shop": {
+                        "localparts": "shop*",
+                        "match": "matches",
+                        "part": "user",
+                        "priority": 1,
+                        "options": { "subaddress": true, "detail-folder": true }
+                    },
+                    "GitHub": {
+                        "localparts": "",
+                        "senders": "github.com",
+                        "options": { "recipients": ["to"] }
+                    },
+                    "Rust": { "localparts": "", "lists": "rust-users.lists.rust-lang.org" }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn build_runs_the_generator_checks() {
+        let domain = super::Domain::new("domain.com")
+            .folder("Newsletter", |f| f.child("Business", ["wallstreet"]))
+            .folder("Work", |f| f.localparts(["wallstreet"]).label("", ["boss"]));
+        let errors = domain
+            .validate(&crate::Options::default())
+            .unwrap_err()
+            .diagnostics
+            .iter()
+            .map(|e| format!("{} {}", e.code(), e.location()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                "E0012 domain.com › Work › labels",
+                "L0004 domain.com › Work"
+            ]
+        );
+
+        /* The global target is checked like generate does. */
+        let domain = super::Domain::new("domain.com").folder("Work", |f| {
+            f.localparts(["^boss$"]).match_type(super::MatchType::Regex)
+        });
+        assert!(domain.validate(&crate::Options::default()).is_ok());
+        let options = crate::Options {
+            target: Some(super::Target::Proton),
+            ..Default::default()
+        };
+        assert_eq!(
+            domain.validate(&options).unwrap_err().to_string(),
+            "error[E0020]: Proton does not support the \"regex\" extension\n  --> domain.com › Work"
+        );
+
+        /* A domain set to a filter keeps it as its self. */
+        let domain = super::Domain {
+            config: super::SieveDomainConfig::SimpleFilter(super::StringOrVec::String(
+                "boss".to_string(),
+            )),
+            ..super::Domain::new("domain.com")
+        }
+        .folder("Work", |f| f.localparts(["work"]));
+        assert_eq!(
+            domain
+                .validate(&crate::Options::default())
+                .unwrap_err()
+                .to_string(),
+            "error[E0008]: 'self' field is not supported at domain level\n  --> domain.com › self"
+        );
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let config = super::Config::parse(
+            include_str!("../examples/sieve.config.json"),
+            super::Format::Json,
+        )
+        .unwrap();
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(
+            json.contains(r#""options":{"mark-as-read":true}"#),
+            "{}",
+            json
+        );
+        let again = super::Config::parse(&json, super::Format::Json).unwrap();
+        assert!(again.diagnostics.is_empty(), "{:?}", again.diagnostics);
+        assert_eq!(serde_json::to_string(&again).unwrap(), json);
+        assert_eq!(
+            crate::generate(&again, &Default::default())
+                .unwrap()
+                .to_string(),
+            crate::generate(&config, &Default::default())
+                .unwrap()
+                .to_string()
+        );
+    }
+}
@@ -0,0 +1,323 @@
+use crate::{source::Span, target::Target};
+use std::fmt;
+
+/*
+ * Everything that can go wrong from reading the config to generating the filters.
+ * Codes are stable, a removed variant retires its code, new variants take a new one.
+ * E codes are always errors, L codes are lints which are warnings by default.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    ReadConfig(String),
+    ParseConfig(String),
+    OptionsNotObject,
+    OptionNotBool(String),
+    InvalidFolderConfig(String),
+    EmptySubConfig,
+    EmptyFolderName,
+    SelfAtDomainLevel,
+    SelfWithoutSubFolder,
+    FullpathWithoutGeneric,
+    FullpathOnWholePath,
+    EmptyString(&'static str),
+    EmptyArray(&'static str),
+    EmptyStringInArray(&'static str),
+    EmptyFolderPath(String),
+    NotStringOrArray(&'static str),
+    LabelsNotObject,
+    InvalidFolderValue,
+    UnreachableFolder,
+    DuplicateLocalpart(String),
+    UnknownKey(String),
+    InvalidOptionValue(String, &'static str),
+    LocalpartClaimedTwice(String, String),
+    GenericCollision(String, String),
+    ShadowedFilter(String, String, String), // Shadowed filter, shadowing filters, example.
+    PartlyShadowedFilter(String, String, String),
+    UnsupportedCapability(String, Target),
+    ScriptTooLarge(usize, usize, Target),
+    RecipientOutsideDomainTest(String),
+    InvalidWildcardDomain,
+}
+
+impl ErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::ReadConfig(_) => "E0001",
+            ErrorKind::ParseConfig(_) => "E0002",
+            ErrorKind::OptionsNotObject => "E0003",
+            ErrorKind::OptionNotBool(_) => "E0004",
+            ErrorKind::InvalidFolderConfig(_) => "E0005",
+            ErrorKind::EmptySubConfig => "E0006",
+            ErrorKind::EmptyFolderName => "E0007",
+            ErrorKind::SelfAtDomainLevel => "E0008",
+            ErrorKind::SelfWithoutSubFolder => "E0009",
+            ErrorKind::FullpathWithoutGeneric => "E0010",
+            ErrorKind::FullpathOnWholePath => "E0011",
+            ErrorKind::EmptyString(_) => "E0012",
+            ErrorKind::EmptyArray(_) => "E0013",
+            ErrorKind::EmptyStringInArray(_) => "E0014",
+            ErrorKind::EmptyFolderPath(_) => "E0015",
+            ErrorKind::NotStringOrArray(_) => "E0016",
+            ErrorKind::LabelsNotObject => "E0017",
+            ErrorKind::InvalidFolderValue => "E0018",
+            ErrorKind::UnreachableFolder => "L0001",
+            ErrorKind::DuplicateLocalpart(_) => "L0002",
+            ErrorKind::UnknownKey(_) => "L0003",
+            ErrorKind::InvalidOptionValue(..) => "E0019",
+            ErrorKind::LocalpartClaimedTwice(..) => "L0004",
+            ErrorKind::GenericCollision(..) => "L0005",
+            ErrorKind::ShadowedFilter(..) => "L0006",
+            ErrorKind::PartlyShadowedFilter(..) => "L0007",
+            ErrorKind::UnsupportedCapability(..) => "E0020",
+            ErrorKind::ScriptTooLarge(..) => "E0021",
+            ErrorKind::RecipientOutsideDomainTest(_) => "L0008",
+            ErrorKind::InvalidWildcardDomain => "E0022",
+        }
+    }
+
+    /*
+     * Attach the location in the config.
+     */
+    pub fn at<T: AsRef<str>>(self, domain: &str, path: &[T]) -> GenerateError {
+        GenerateError {
+            severity: if self.code().starts_with('L') {
+                Severity::Warning
+            } else {
+                Severity::Error
+            },
+            domain: domain.to_string(),
+            path: path.iter().map(|s| s.as_ref().to_string()).collect(),
+            kind: self,
+            span: None,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::ReadConfig(e) => write!(f, "cannot read {}", e),
+            ErrorKind::ParseConfig(e) => write!(f, "cannot parse config, {}", e),
+            ErrorKind::OptionsNotObject => write!(f, "options must be an object"),
+            ErrorKind::OptionNotBool(name) => write!(f, "{} option must be bool", name),
+            ErrorKind::InvalidFolderConfig(e) => write!(f, "invalid folder config, {}", e),
+            ErrorKind::EmptySubConfig => write!(f, "sub-config cannot be empty"),
+            ErrorKind::EmptyFolderName => write!(f, "empty string cannot be used for folder name"),
+            ErrorKind::SelfAtDomainLevel => {
+                write!(f, "'self' field is not supported at domain level")
+            }
+            ErrorKind::SelfWithoutSubFolder => {
+                write!(f, "'self' field is useless if there is no sub-folder")
+            }
+            ErrorKind::FullpathWithoutGeneric => write!(
+                f,
+                "not generating generic filters, set fullpath option is useless"
+            ),
+            ErrorKind::FullpathOnWholePath => {
+                write!(
+                    f,
+                    "folder is the whole path, set fullpath option is useless"
+                )
+            }
+            ErrorKind::EmptyString(name) => write!(f, "{} cannot be empty string", name),
+            ErrorKind::EmptyArray(name) => write!(f, "array of {} cannot be empty", name),
+            ErrorKind::EmptyStringInArray(name) => {
+                write!(f, "array of {} cannot contain empty string", name)
+            }
+            ErrorKind::EmptyFolderPath(name) => write!(
+                f,
+                "calling {}.generate() with empty folder path, something is wrong",
+                name
+            ),
+            ErrorKind::NotStringOrArray(name) => {
+                write!(f, "{} must be a string or an array of strings", name)
+            }
+            ErrorKind::LabelsNotObject => write!(f, "labels must be an object"),
+            ErrorKind::InvalidFolderValue => write!(
+                f,
+                "folder config must be a string, an array of strings or an object"
+            ),
+            ErrorKind::UnreachableFolder => write!(
+                f,
+                "folder has no localparts and no generic filter, nothing will be filtered into it"
+            ),
+            ErrorKind::DuplicateLocalpart(localpart) => {
+                write!(f, "localpart \"{}\" is listed more than once", localpart)
+            }
+            ErrorKind::UnknownKey(key) => write!(f, "unknown key \"{}\" is ignored", key),
+            ErrorKind::InvalidOptionValue(name, expected) => {
+                write!(f, "{} option must be {}", name, expected)
+            }
+            ErrorKind::LocalpartClaimedTwice(localpart, other) => write!(
+                f,
+                "localpart \"{}\" is already claimed by {}, only one of them will get the mail",
+                localpart, other
+            ),
+            ErrorKind::GenericCollision(localpart, other) => write!(
+                f,
+                "generic localpart \"{}\" is also generated by {}, only one of them will get the mail, use fullpath or the auto-fullpath domain option",
+                localpart, other
+            ),
+            ErrorKind::ShadowedFilter(filter, by, example) => write!(
+                f,
+                "{} is never reached, every address it matches is taken by {} first, e.g. {}",
+                filter, by, example
+            ),
+            ErrorKind::PartlyShadowedFilter(filter, by, example) => write!(
+                f,
+                "some addresses of the {} are taken by {} first, e.g. {}",
+                filter, by, example
+            ),
+            ErrorKind::UnsupportedCapability(capability, target) => write!(
+                f,
+                "{} does not support the \"{}\" extension",
+                target, capability
+            ),
+            ErrorKind::ScriptTooLarge(size, limit, target) => write!(
+                f,
+                "the script is {} bytes, {} accepts at most {}",
+                size, target, limit
+            ),
+            ErrorKind::RecipientOutsideDomainTest(recipient) => write!(
+                f,
+                "\"{}\" is not in the domain's recipients, a mail only sent there never gets to this filter",
+                recipient
+            ),
+            ErrorKind::InvalidWildcardDomain => write!(
+                f,
+                "a wildcard domain must start with \"*.\", as in *.domain.com"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/*
+ * Warnings are reported the same way, only their severity differs.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GenerateError {
+    pub severity: Severity,
+    pub domain: String,
+    pub path: Vec<String>, // Folders then keys, e.g. [Utilities, Grocery, labels]
+    pub kind: ErrorKind,
+    pub span: Option<Span>, // Set when known without the path, e.g. parse errors.
+}
+
+impl GenerateError {
+    pub fn code(&self) -> &'static str {
+        self.kind.code()
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn with_severity(mut self, severity: Severity) -> Self {
+        self.severity = severity;
+        self
+    }
+
+    /*
+     * domain.com › Utilities › Grocery › labels
+     */
+    pub fn location(&self) -> String {
+        location(&self.domain, &self.path)
+    }
+}
+
+pub fn location(domain: &str, path: &[String]) -> String {
+    std::iter::once(domain)
+        .filter(|domain| !domain.is_empty())
+        .chain(
+            path.iter()
+                .map(|key| if key.is_empty() { "\"\"" } else { key }),
+        )
+        .collect::<Vec<_>>()
+        .join(" › ")
+}
+
+impl fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code(), self.kind)?;
+        let location = self.location();
+        if !location.is_empty() {
+            write!(f, "\n  --> {}", location)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for GenerateError {}
+
+/*
+ * A config that can't be generated, with every error and warning found.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub diagnostics: Vec<GenerateError>,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, e) in self.diagnostics.iter().enumerate() {
+            if i > 0 {
+                f.write_str("\n\n")?;
+            }
+            write!(f, "{}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::ErrorKind;
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            ErrorKind::EmptyString("label")
+                .at("domain.com", &["Utilities", "Grocery", "labels"])
+                .to_string(),
+            "error[E0012]: label cannot be empty string\n  --> domain.com › Utilities › Grocery › labels"
+        );
+    }
+
+    #[test]
+    fn display_warning() {
+        assert_eq!(
+            ErrorKind::UnknownKey("mark_as-read".to_string())
+                .at("domain.com", &["Utilities", "options", "mark_as-read"])
+                .to_string(),
+            "warning[L0003]: unknown key \"mark_as-read\" is ignored\n  --> domain.com › Utilities › options › mark_as-read"
+        );
+    }
+
+    #[test]
+    fn display_without_location() {
+        assert_eq!(
+            ErrorKind::ReadConfig("sieve.config.json, no such file".to_string())
+                .at::<&str>("", &[])
+                .to_string(),
+            "error[E0001]: cannot read sieve.config.json, no such file"
+        );
+    }
+}
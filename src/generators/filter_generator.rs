@@ -1,200 +1,420 @@
-use crate::common::{code_block, is_unknown, FilterOptions, FullFilter, StringOrVec};
+use super::shadowing::Branch;
+use crate::{
+    common::{escape_glob, is_under, FilterOptions, FullFilter, MatchType, Part, StringOrVec},
+    error::{ErrorKind, GenerateError},
+    sieve,
+    target::Profile,
+};
 use std::{
-    collections::{BTreeMap, HashSet},
-    fmt::{self, Display},
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet},
 };
 
+/*
+ * A filter once its localparts and options are resolved.
+ */
+type Filter = FullFilter<Vec<String>, FilterOptions<bool>>;
+
+/*
+ * What the filters of a generator test, the localparts of a filter are
+ * the values of that field.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Localparts, // envelope :localpart "to"
+    Senders,    // address :domain or :all "from"
+    Lists,      // header :contains "list-id"
+}
+
 #[derive(Debug)]
 pub struct FilterGenerator<'a> {
     name: &'a str, // Name of the generator
+    domain: String,
     domain_folder: String,
-    filters: BTreeMap<String, FullFilter<Vec<String>, FilterOptions<bool>>>,
-    begin_with_else: bool, // It can begin with else if generated after another.
+    filters: BTreeMap<String, Filter>,
+    notes: BTreeMap<String, String>, // Comment at the top of the filter of a path.
+    config_paths: BTreeMap<String, Vec<String>>,
+    profile: Profile,
+    field: Field,
+    unknown: Option<String>, // Folder of the unknown mails, None if they aren't filed.
 }
 
 impl<'a> FilterGenerator<'a> {
     //
-    pub fn new(name: &'a str, domain_folder: String, begin_with_else: bool) -> FilterGenerator<'a> {
+    pub fn new(
+        name: &'a str,
+        domain: &str,
+        domain_folder: String,
+        profile: Profile,
+    ) -> FilterGenerator<'a> {
         FilterGenerator {
             name,
+            domain: domain.to_string(),
             domain_folder,
             filters: BTreeMap::new(),
-            begin_with_else,
+            notes: BTreeMap::new(),
+            config_paths: BTreeMap::new(),
+            profile,
+            field: Field::Localparts,
+            unknown: None,
+        }
+    }
+
+    pub fn field(mut self, field: Field) -> Self {
+        self.field = field;
+        self
+    }
+
+    pub fn unknown(mut self, folder: Option<&str>) -> Self {
+        self.unknown = folder.map(str::to_string);
+        self
+    }
+
+    /*
+     * Key of the filtered values in the config, for errors.
+     */
+    fn key(&self) -> &'static str {
+        match self.field {
+            Field::Localparts => "localparts",
+            Field::Senders => "senders",
+            Field::Lists => "lists",
         }
     }
 
+    pub fn note(&mut self, path: &str, note: String) -> &mut Self {
+        self.notes.insert(path.to_string(), note);
+        self
+    }
+
+    /*
+     * config_path is where the filter is in the config, only used to report errors.
+     */
     pub fn generate(
         &mut self,
         path: &str,
+        config_path: &[String],
         full_filter: FullFilter<StringOrVec, FilterOptions<bool>>,
-    ) -> &mut Self {
+    ) -> Result<&mut Self, Vec<GenerateError>> {
         //
+        let error_at = |kind: ErrorKind, keys: &[&str]| {
+            kind.at(
+                &self.domain,
+                &config_path
+                    .iter()
+                    .map(String::as_str)
+                    .chain(keys.iter().copied())
+                    .collect::<Vec<_>>(),
+            )
+        };
         if path.is_empty() {
-            panic!(
-                "ERROR: calling {}.generate() with empty folder path, something is wrong.",
-                self.name
-            );
+            return Err(vec![error_at(
+                ErrorKind::EmptyFolderPath(self.name.to_string()),
+                &[],
+            )]);
         }
         if let StringOrVec::String(localpart) = &full_filter.localparts {
-            if localpart.is_empty() {
-                return self; // Filters localpart can take an empty string to use only generic.
+            if localpart.is_empty() && self.field == Field::Localparts {
+                return Ok(self); // Filters localpart can take an empty string to use only generic.
             }
         }
 
+        /* Check everything before returning so all errors of the filter are reported at once. */
+        let mut errors = vec![];
         /* Convert StringOrVec to Vec before insert for easier to_string(). */
-        let localparts: Vec<String> = full_filter.localparts.panic_on_empty("localparts").into();
+        let localparts: Vec<String> = match full_filter.localparts.error_on_empty(self.key()) {
+            Ok(localparts) => localparts.into(),
+            Err(kind) => {
+                errors.push(error_at(kind, &[self.key()]));
+                vec![]
+            }
+        };
         let labels = if let Some(full_filter_labels) = full_filter.labels {
             let mut labels = BTreeMap::new();
             for (label, keywords) in full_filter_labels.into_iter() {
-                StringOrVec::String(label.clone()).panic_on_empty("label");
-                labels.insert(
-                    label,
-                    Vec::<String>::from(keywords.panic_on_empty("label keywords")),
-                );
+                if let Err(kind) = StringOrVec::String(label.clone()).error_on_empty("label") {
+                    errors.push(error_at(kind, &["labels"]));
+                }
+                match keywords.error_on_empty("label keywords") {
+                    Ok(keywords) => {
+                        labels.insert(label, Vec::<String>::from(keywords));
+                    }
+                    Err(kind) => errors.push(error_at(kind, &["labels", &label])),
+                }
             }
             Some(labels)
         } else {
             None
         };
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        self.config_paths
+            .insert(path.to_string(), config_path.to_vec());
         self.filters.insert(
             path.to_string(),
-            FullFilter::<Vec<String>, FilterOptions<bool>> {
+            Filter {
                 localparts,
+                senders: None,
+                lists: None,
+                match_type: full_filter.match_type,
+                part: full_filter.part,
+                priority: full_filter.priority,
                 labels,
                 options: full_filter.options,
             },
         );
 
-        self
+        Ok(self)
     }
 
-    pub fn to_string_with_unknown(&self) -> String {
-        self.to_string()
-            + " else {"
-            + &code_block("\naddflag \"\\\\Seen\";\nfileinto \"Unknown\";")
-            + "\n}"
+    /*
+     * Order of the generated script, reversed path order unless a priority says otherwise.
+     * rev() is for generic filter, A/B must be filtered before A, otherwise a.b will all go to A, not A/B
+     * The sort is stable, filters of the same priority (0 by default) keep that order.
+     */
+    fn ordered(&self) -> Vec<(&String, &Filter)> {
+        let mut filters = self.filters.iter().rev().collect::<Vec<_>>();
+        filters.sort_by_key(|(_, full_filter)| Reverse(full_filter.priority.unwrap_or(0)));
+        filters
     }
-}
 
-impl Display for FilterGenerator<'_> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut result = "".to_string();
-        /* rev() is for generic filter, A/B must be filtered before A, otherwise a.b will all go to A, not A/B */
-        for (i, (path, full_filter)) in self.filters.iter().rev().enumerate() {
-            result = result
-                + &if i == 0 {
-                    format!(
-                        "\n# {} filters\n{}if",
-                        self.name,
-                        if !self.begin_with_else { "" } else { "els" }
-                    )
+    pub fn branches(&self) -> Vec<Branch<'_>> {
+        self.ordered()
+            .into_iter()
+            .map(|(path, full_filter)| Branch {
+                path,
+                generator: self.name,
+                config_path: &self.config_paths[path],
+                localparts: &full_filter.localparts,
+                match_type: full_filter.match_type,
+                part: full_filter.part.unwrap_or(Part::Localpart),
+                recipients: full_filter.options.recipients.unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /*
+     * One if branch per filter, the first one has a comment with the name of the generator.
+     */
+    pub fn to_sieve(&self) -> Vec<sieve::Branch> {
+        self.ordered()
+            .into_iter()
+            .enumerate()
+            .map(|(i, (path, full_filter))| {
+                let match_type = match full_filter.match_type {
+                    Some(MatchType::Is) => sieve::MatchType::Is,
+                    Some(MatchType::Regex) => sieve::MatchType::Regex,
+                    Some(MatchType::Matches) | None => sieve::MatchType::Matches,
+                };
+                let keys = match full_filter.match_type {
+                    None if self.field == Field::Senders => full_filter
+                        .localparts
+                        .iter()
+                        .map(|sender| escape_sender(sender))
+                        .collect(),
+                    None => full_filter
+                        .localparts
+                        .iter()
+                        .map(|l| escape_glob(l))
+                        .collect(),
+                    Some(_) => full_filter.localparts.clone(),
+                };
+                let branch = sieve::Branch::new(
+                    match self.field {
+                        Field::Localparts => self.profile.recipient(
+                            match full_filter.part {
+                                Some(Part::Localpart) | None => sieve::AddressPart::Localpart,
+                                Some(Part::User) => sieve::AddressPart::User,
+                                Some(Part::Detail) => sieve::AddressPart::Detail,
+                            },
+                            match_type,
+                            keys.into(),
+                            full_filter.options.recipients.unwrap_or_default(),
+                        ),
+                        Field::Senders => sender(match_type, keys),
+                        /* List-Ids are looked for as they are, whatever the match. */
+                        Field::Lists => sieve::Test::Header {
+                            match_type: sieve::MatchType::Contains,
+                            headers: "list-id".into(),
+                            keys: full_filter.localparts.clone().into(),
+                        },
+                    },
+                    self.commands(path, full_filter),
+                );
+                if i == 0 {
+                    branch.with_comment(&format!("{} filters", self.name))
                 } else {
-                    " elsif".to_string()
+                    branch
                 }
-                + " envelope :localpart :matches \"to\" "
-                + &serde_json::to_string(&full_filter.localparts).unwrap()
-                + " {"
-                + &code_block({
-                    let mut cumulated_path = "".to_string();
-                    let mut file_into = "".to_string();
-                    /*
-                     * Fileinto from parent to child, this allow the mail to fallback
-                     * to one of the parent folder in case the child doesn't exist
-                    */
-                    for folder in path.split('/').collect::<Vec<_>>() {
-                        cumulated_path = if cumulated_path.is_empty() {
-                            folder.to_string()
-                        } else {
-                            format!("{}/{}", cumulated_path, folder)
-                        };
-                        file_into = file_into
-                            + &format!(
-                                "\nfileinto \"{}{}\";",
-                                self.domain_folder, cumulated_path
-                            )
-                    }
-                    file_into
-                })
-
-                /* Generate sieve code for labels. */
-                + &code_block({
-                    let mut all_keywords = HashSet::new();
-                    let mark_as_read = if full_filter.options.mark_as_read {
-                        "\naddflag \"\\\\Seen\";".to_string() +
-                        if !is_unknown(&path) {
-                            "\nfileinto \"unread\";"
-                        } else {
-                            ""
-                        }
-                    } else {
-                        "".to_string()
-                    };
-                    const IF_HEADER_CONTAINS: &str = "\nif header :contains [\"from\",\"subject\"] ";
-                    let labels = if let Some(full_filter_labels) = &full_filter.labels {
-                        /*
-                         * 1 mail can have multiple labels, thus we cannot use if else but only if
-                         * ```
-                         * if () {
-                         *     fileinto label 1
-                         * }
-                         * if () {
-                         *     fileinto label 2
-                         * }
-                         * else {
-                         *     mark as seen
-                         * }
-                         * ```
-                         * but we need an else to mark as read if no label condition is met (by default label overwrite mark-as-read option). This wouldn't
-                         * work since else apply only to the last if. Therefore we need
-                         * this flag to know when to wrap those if in a big if that contains
-                         * all the keywords.
-                        */
-                        let multiple_labels = full_filter_labels.len() > 1;
-                        let mut labels = "".to_string();
-                        for (label, keywords) in full_filter_labels.iter() {
-                            labels = labels
-                                + IF_HEADER_CONTAINS
-                                + &serde_json::to_string(&keywords).unwrap()
-                                + " {"
-                                + &code_block(format!("\nfileinto \"{}\";", label))
-                                + "\n}";
-                            /* If we indeed need to wrap the if then we need to store all the keywords. */
-                            if !mark_as_read.is_empty() && multiple_labels {
-                                keywords.iter().for_each(|keyword| {
-                                    all_keywords.insert(keyword.clone());
-                                })
-                            }
-                        }
-                        labels
-                    } else {
-                        "".to_string()
-                    };
-                    /* If not mark-as-read then just show all the if. */
-                    if mark_as_read.is_empty() {
-                        labels
-                    /* Else then if no labels just show mark as read. */
-                    } else if labels.is_empty() {
-                        mark_as_read
-                    /* Else both are there. */
-                    } else {
-                        /* There's all_keywords implies that there's multiple labels, need to wrap it. */
-                        let mut all_keywords = all_keywords.drain().collect::<Vec<String>>();
-                        all_keywords.sort();
-                        (if !all_keywords.is_empty() {
-                            IF_HEADER_CONTAINS.to_string()
-                                + &serde_json::to_string(&all_keywords).unwrap()
-                                + " {"
-                                + &code_block(labels)
-                                + "\n}"
-                        } else {
-                            labels
-                        }) + " else {"
-                            + &code_block(mark_as_read)
-                            + "\n}"
-                    }
-                })
-                + "\n}";
+            })
+            .collect()
+    }
+
+    /*
+     * Fileinto from parent to child, this allow the mail to fallback
+     * to one of the parent folder in case the child doesn't exist
+     */
+    pub fn file_into(&self, path: &str) -> Vec<sieve::Command> {
+        let mut commands = vec![];
+        let mut cumulated_path = "".to_string();
+        for folder in path.split('/') {
+            cumulated_path = if cumulated_path.is_empty() {
+                folder.to_string()
+            } else {
+                format!("{}/{}", cumulated_path, folder)
+            };
+            commands.push(sieve::Command::FileInto(
+                self.profile
+                    .folder(&format!("{}{}", self.domain_folder, cumulated_path)),
+            ));
         }
-        write!(f, "{}", result)
+        commands
+    }
+
+    fn commands(&self, path: &str, full_filter: &Filter) -> Vec<sieve::Command> {
+        let mut commands = vec![];
+        if let Some(note) = self.notes.get(path) {
+            commands.push(sieve::Command::Comment(note.clone()));
+        }
+        commands.extend(self.file_into(path));
+        /*
+         * "?*" leaves out an empty detail, ${1} is its first character and ${2} the rest.
+         * The detail's folder doesn't exist before its first mail, it is created then.
+         */
+        if full_filter.options.detail_folder {
+            commands.push(sieve::Command::If(sieve::If::new(
+                vec![sieve::Branch::new(
+                    self.profile.recipient(
+                        sieve::AddressPart::Detail,
+                        sieve::MatchType::Matches,
+                        "?*".into(),
+                        full_filter.options.recipients.unwrap_or_default(),
+                    ),
+                    vec![sieve::Command::FileIntoCreate(self.profile.folder(
+                        &format!("{}{}/${{1}}${{2}}", self.domain_folder, path),
+                    ))],
+                )],
+                None,
+            )));
+        }
+
+        /* Generate sieve code for labels. */
+        let mut mark_as_read = vec![];
+        if full_filter.options.mark_as_read {
+            mark_as_read.push(sieve::Command::AddFlag("\\Seen".to_string()));
+            if !self
+                .unknown
+                .as_deref()
+                .is_some_and(|unknown| is_under(path, unknown))
+            {
+                mark_as_read.push(sieve::Command::FileInto("unread".to_string()));
+            }
+        }
+        let if_header_contains = |keywords: Vec<String>, commands: Vec<sieve::Command>| {
+            sieve::Branch::new(
+                sieve::Test::Header {
+                    match_type: sieve::MatchType::Contains,
+                    headers: vec!["from", "subject"].into(),
+                    keys: keywords.into(),
+                },
+                commands,
+            )
+        };
+        let labels = full_filter.labels.clone().unwrap_or_default();
+        /*
+         * 1 mail can have multiple labels, thus we cannot use if else but only if
+         * ```
+         * if () {
+         *     fileinto label 1
+         * }
+         * if () {
+         *     fileinto label 2
+         * }
+         * else {
+         *     mark as seen
+         * }
+         * ```
+         * but we need an else to mark as read if no label condition is met (by default label overwrite mark-as-read option). This wouldn't
+         * work since else apply only to the last if. Therefore those if are
+         * wrapped in a big if that contains all the keywords.
+         */
+        let mut label_commands = labels
+            .iter()
+            .map(|(label, keywords)| {
+                sieve::Command::If(sieve::If::new(
+                    vec![if_header_contains(
+                        keywords.clone(),
+                        vec![sieve::Command::FileInto(self.profile.folder(label))],
+                    )],
+                    None,
+                ))
+            })
+            .collect::<Vec<_>>();
+        /* If not mark-as-read then just show all the if. */
+        if mark_as_read.is_empty() {
+            commands.extend(label_commands);
+        /* Else then if no labels just show mark as read. */
+        } else if label_commands.is_empty() {
+            commands.extend(mark_as_read);
+        /* Else both are there, a single label gets the else itself. */
+        } else if label_commands.len() == 1 {
+            if let Some(sieve::Command::If(mut if_)) = label_commands.pop() {
+                if_.otherwise = Some(mark_as_read);
+                commands.push(sieve::Command::If(if_));
+            }
+        } else {
+            let all_keywords = labels
+                .values()
+                .flatten()
+                .cloned()
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect();
+            commands.push(sieve::Command::If(sieve::If::new(
+                vec![if_header_contains(all_keywords, label_commands)],
+                Some(mark_as_read),
+            )));
+        }
+        commands
+    }
+}
+
+/*
+ * Senders are literal too, but *@domain.com and *.domain.com are kept as wildcards,
+ * any address of a domain and any of its subdomains, they mean nothing literally.
+ */
+fn escape_sender(sender: &str) -> String {
+    match sender.strip_prefix('*') {
+        Some(rest) if rest.starts_with('@') || rest.starts_with('.') => {
+            format!("*{}", escape_glob(rest))
+        }
+        _ => escape_glob(sender),
+    }
+}
+
+/*
+ * address :domain "from" for the senders without @, address :all "from" for the
+ * addresses, anyof both if there are both.
+ */
+fn sender(match_type: sieve::MatchType, senders: Vec<String>) -> sieve::Test {
+    let (addresses, domains): (Vec<_>, Vec<_>) =
+        senders.into_iter().partition(|sender| sender.contains('@'));
+    let mut tests = vec![
+        (sieve::AddressPart::Domain, domains),
+        (sieve::AddressPart::All, addresses),
+    ]
+    .into_iter()
+    .filter(|(_, keys)| !keys.is_empty())
+    .map(|(address_part, keys)| sieve::Test::Address {
+        address_part,
+        match_type,
+        headers: "from".into(),
+        keys: keys.into(),
+    })
+    .collect::<Vec<_>>();
+    if tests.len() == 1 {
+        tests.remove(0)
+    } else {
+        sieve::Test::AnyOf(tests)
     }
 }
@@ -1,47 +1,282 @@
 mod filter_generator;
-use std::fmt;
+mod shadowing;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    sync::LazyLock,
+};
 
-use crate::common::{is_unknown, FilterOptions, FullFilter, SieveDomainConfig, StringOrVec};
-use filter_generator::FilterGenerator;
+use crate::{
+    common::{
+        escape_glob, is_under, CatchAll, DomainOptions, FilterOptions, FullFilter, MatchType, Part,
+        Recipient, Recipients, SieveDomainConfig, StringOrVec, SUBDOMAIN,
+    },
+    error::{location, ErrorKind, GenerateError, Severity},
+    sieve,
+    target::{Profile, Target},
+};
+use filter_generator::{Field, FilterGenerator};
 use regex::Regex;
 
 pub struct DomainGenerator<'a> {
+    domain: String,
+    diagnostics: Vec<GenerateError>, // Collected along the way to report all of them at once.
+    reported: HashSet<GenerateError>, // The same, to report each one once.
+    claims: BTreeMap<(String, Recipients), Vec<Vec<String>>>, // Lowercased localpart and where it's looked for -> config paths listing it.
+    duplicate_localparts: Severity,
+    auto_fullpath: bool,
+    generics: Vec<GenericFilter>, // Generated last, once the collisions are known.
+    target: Option<Target>,
+    profile: Profile,
+    lists_folder: Option<String>,
+    recipients: Recipients,
+    unknown: CatchAll,
+    unknown_mark_as_read: bool,
+    unknown_by_localpart: bool,
+    sender_filter_generator: FilterGenerator<'a>,
+    list_filter_generator: FilterGenerator<'a>,
     custom_filter_generator: FilterGenerator<'a>,
     generic_filter_generator: FilterGenerator<'a>,
 }
 
 impl DomainGenerator<'_> {
     //
-    pub fn new(domain: &str, domain_as_first_folder: bool) -> Self {
-        let domain_folder = if domain_as_first_folder {
-            format!("@{domain}/")
+    pub fn new(domain: &str, options: &DomainOptions) -> Self {
+        /* A wildcard domain's folder is its base domain's, the subdomain can follow it. */
+        let mut domain_folder = if options.domain_as_first_folder {
+            format!("@{}/", domain.strip_prefix("*.").unwrap_or(domain))
         } else {
             String::from("")
         };
+        if options.subdomain_as_first_folder {
+            domain_folder.push_str(&format!("${{{}}}/", SUBDOMAIN));
+        }
+        let profile = options
+            .target
+            .map_or_else(Profile::default, |t| t.profile());
+        let unknown = options.unknown.folder();
+        let generator = |name, field| {
+            FilterGenerator::new(name, domain, domain_folder.clone(), profile)
+                .field(field)
+                .unknown(unknown)
+        };
         DomainGenerator {
-            custom_filter_generator: FilterGenerator::new("Custom", domain_folder.clone(), false),
-            generic_filter_generator: FilterGenerator::new("Generic", domain_folder, true),
+            domain: domain.to_string(),
+            diagnostics: vec![],
+            reported: HashSet::new(),
+            claims: BTreeMap::new(),
+            duplicate_localparts: options.duplicate_localparts,
+            auto_fullpath: options.auto_fullpath,
+            generics: vec![],
+            target: options.target,
+            profile,
+            lists_folder: options.lists_folder.clone(),
+            recipients: options.recipients,
+            unknown: options.unknown.clone(),
+            unknown_mark_as_read: options.unknown_mark_as_read,
+            unknown_by_localpart: options.unknown_by_localpart,
+            sender_filter_generator: generator("Sender", Field::Senders),
+            list_filter_generator: generator("List", Field::Lists),
+            custom_filter_generator: generator("Custom", Field::Localparts),
+            generic_filter_generator: generator("Generic", Field::Localparts),
+        }
+    }
+
+    /*
+     * Walk the whole config before failing, every error found is returned along with the warnings.
+     * Ok only has warnings.
+     */
+    pub fn generate(
+        &mut self,
+        sieve_domain_config: SieveDomainConfig,
+    ) -> Result<Vec<GenerateError>, Vec<GenerateError>> {
+        self._generate("", &[], sieve_domain_config);
+        self.lint_claims();
+        self.generate_generics();
+        self.lint_shadowing();
+        self.check_target();
+        let diagnostics = std::mem::take(&mut self.diagnostics);
+        self.reported.clear();
+        if diagnostics.iter().any(GenerateError::is_error) {
+            Err(diagnostics)
+        } else {
+            Ok(diagnostics)
+        }
+    }
+
+    pub fn profile(&self) -> Profile {
+        self.profile
+    }
+
+    /*
+     * Error or warning, depending on the kind.
+     */
+    fn error(&mut self, kind: ErrorKind, config_path: &[String]) {
+        let error = kind.at(&self.domain, config_path);
+        self.errors(Err(vec![error]));
+    }
+
+    fn errors(&mut self, result: Result<(), Vec<GenerateError>>) {
+        if let Err(errors) = result {
+            for error in errors {
+                /* Labels are checked by every generator of the folder. */
+                if self.reported.insert(error.clone()) {
+                    self.diagnostics.push(error);
+                }
+            }
+        }
+    }
+
+    /*
+     * Lints of the localparts of a folder, once its options are known.
+     */
+    fn lint(
+        &mut self,
+        path: &str,
+        config_path: &[String],
+        localparts: &StringOrVec,
+        part: Option<Part>,
+        options: FilterOptions<bool>,
+        has_other_filters: bool, // Senders or lists.
+    ) {
+        let recipients = options.recipients.unwrap_or(self.recipients);
+        /* Details are claimed apart, shop+amazon is claimed as "+amazon". */
+        let claimed = |localpart: &str| match part {
+            Some(Part::Detail) => format!("+{}", localpart),
+            Some(Part::Localpart | Part::User) | None => localpart.to_string(),
+        };
+        match localparts {
+            StringOrVec::String(localpart) => {
+                if localpart.is_empty() {
+                    if !options.generic && !has_other_filters && Some(path) != self.unknown.folder()
+                    {
+                        self.error(ErrorKind::UnreachableFolder, config_path);
+                    }
+                } else {
+                    self.claim(&claimed(localpart), recipients, config_path);
+                }
+            }
+            StringOrVec::Vec(localparts) => {
+                let mut seen = HashSet::new();
+                for localpart in localparts {
+                    if !seen.insert(localpart) {
+                        self.error(
+                            ErrorKind::DuplicateLocalpart(localpart.clone()),
+                            config_path,
+                        );
+                    } else {
+                        self.claim(&claimed(localpart), recipients, config_path);
+                    }
+                }
+            }
         }
     }
 
-    pub fn generate(&mut self, sieve_domain_config: SieveDomainConfig) -> &mut Self {
-        self._generate("", sieve_domain_config)
+    /*
+     * The domain test comes first, a folder can only narrow what it selects. Without
+     * envelope, the envelope is the to header.
+     */
+    fn lint_recipients(&mut self, recipients: Recipients, config_path: &[String]) {
+        let config_path = [config_path, &["options".into(), "recipients".into()]].concat();
+        for recipient in recipients.iter() {
+            let covered = if self.profile.envelope {
+                self.recipients.contains(recipient)
+            } else {
+                self.recipients
+                    .iter()
+                    .any(|domain_recipient| domain_recipient.header() == recipient.header())
+            };
+            if !covered {
+                self.error(
+                    ErrorKind::RecipientOutsideDomainTest(
+                        match recipient {
+                            Recipient::Envelope => "envelope",
+                            _ => recipient.header(),
+                        }
+                        .to_string(),
+                    ),
+                    &config_path,
+                );
+            }
+        }
+    }
+
+    /*
+     * Sieve compares localparts case-insensitively by default. The same localpart
+     * looked for in other recipients is another address, as for the shadowing.
+     */
+    fn claim(&mut self, localpart: &str, recipients: Recipients, config_path: &[String]) {
+        self.claims
+            .entry((localpart.to_lowercase(), recipients))
+            .or_default()
+            .push(config_path.to_vec());
+    }
+
+    /*
+     * A localpart listed by several folders only reaches the one whose filter comes first
+     * in the script, every other folder is reported. Folders whose filter couldn't be
+     * generated come last.
+     */
+    fn lint_claims(&mut self) {
+        let order = self
+            .custom_filter_generator
+            .branches()
+            .iter()
+            .enumerate()
+            .map(|(i, branch)| (branch.config_path.to_vec(), i))
+            .collect::<HashMap<_, _>>();
+        for ((localpart, _), mut config_paths) in std::mem::take(&mut self.claims) {
+            if config_paths.len() < 2 {
+                continue;
+            }
+            config_paths.sort_by(|a, b| {
+                (order.get(a).unwrap_or(&usize::MAX), a)
+                    .cmp(&(order.get(b).unwrap_or(&usize::MAX), b))
+            });
+            let first = location(&self.domain, &config_paths[0]);
+            for config_path in &config_paths[1..] {
+                let error = ErrorKind::LocalpartClaimedTwice(localpart.clone(), first.clone())
+                    .at(&self.domain, config_path)
+                    .with_severity(self.duplicate_localparts);
+                self.errors(Err(vec![error]));
+            }
+        }
     }
 
-    fn _generate(&mut self, path: &str, sub_config: SieveDomainConfig) -> &mut Self {
+    /*
+     * path is the folder path, config_path is where we are in the config
+     * which also contains 'self' and is used to report errors.
+     */
+    fn _generate(
+        &mut self,
+        path: &str,
+        config_path: &[String],
+        sub_config: SieveDomainConfig,
+    ) -> &mut Self {
         /* Also need for generic filter. */
         let mut labels = None;
-        let mut options = if !is_unknown(path) {
+        let mut priority = None;
+        let mut options = if !self
+            .unknown
+            .folder()
+            .is_some_and(|unknown| is_under(path, unknown))
+        {
             FilterOptions::<bool> {
-                generic: true,       // Default
-                fullpath: false,     // Default
-                mark_as_read: false, // Default
+                generic: true,        // Default
+                fullpath: false,      // Default
+                mark_as_read: false,  // Default
+                subaddress: false,    // Default
+                detail_folder: false, // Default
+                recipients: Some(self.recipients),
             }
         } else {
             FilterOptions::<bool> {
-                generic: false,     // No generic filter for Unknown.
-                fullpath: false,    // Ignored
-                mark_as_read: true, // Everything under Unknown is marked as read.
+                generic: false,                          // No generic filter for Unknown.
+                fullpath: false,                         // Ignored
+                mark_as_read: self.unknown_mark_as_read, // Everything under Unknown is marked as read.
+                subaddress: false,                       // Ignored
+                detail_folder: false,                    // Default
+                recipients: Some(self.recipients),
             }
         };
         /********************************/
@@ -49,62 +284,122 @@ impl DomainGenerator<'_> {
         /* Custom filter */
         match sub_config {
             SieveDomainConfig::SimpleFilter(localparts) => {
-                self.custom_filter_generator.generate(
-                    path,
-                    FullFilter {
-                        localparts,
-                        labels: None,
-                        options,
-                    },
-                );
+                self.lint(path, config_path, &localparts, None, options, false);
+                let result = self
+                    .custom_filter_generator
+                    .generate(
+                        path,
+                        config_path,
+                        FullFilter {
+                            localparts,
+                            senders: None,
+                            lists: None,
+                            match_type: None,
+                            part: None,
+                            priority: None,
+                            labels: None,
+                            options,
+                        },
+                    )
+                    .map(|_| ());
+                self.errors(result);
             }
             SieveDomainConfig::FullFilter(full_filter) => {
                 labels = full_filter.labels.clone();
+                priority = full_filter.priority;
                 if let Some(full_filter_options) = full_filter.options {
                     options = full_filter_options.unwrap_or_default(options);
+                    if let Some(recipients) = full_filter_options.recipients {
+                        self.lint_recipients(recipients, config_path);
+                    }
                     if full_filter_options.fullpath.is_some() {
+                        let fullpath_config_path =
+                            [config_path, &["options".into(), "fullpath".into()]].concat();
                         if !options.generic {
-                            panic!(
-                                "ERROR: Not generating generic filters for {}, set fullpath option is useless.",
-                                path
-                            );
-                        }
-                        if !path.contains('/') {
-                            panic!(
-                                "ERROR: {} is the whole path, set fullpath option is useless.",
-                                path
-                            );
+                            self.error(ErrorKind::FullpathWithoutGeneric, &fullpath_config_path);
+                        } else if !path.contains('/') {
+                            self.error(ErrorKind::FullpathOnWholePath, &fullpath_config_path);
                         }
                     }
                 }
-                self.custom_filter_generator.generate(
+                self.lint(
                     path,
-                    FullFilter::<StringOrVec, FilterOptions<bool>> {
-                        localparts: full_filter.localparts,
-                        labels: full_filter.labels,
-                        options,
-                    },
+                    config_path,
+                    &full_filter.localparts,
+                    full_filter.part,
+                    options,
+                    full_filter.senders.is_some() || full_filter.lists.is_some(),
                 );
+                let mut results = vec![];
+                for (generator, values) in [
+                    (&mut self.sender_filter_generator, full_filter.senders),
+                    (&mut self.list_filter_generator, full_filter.lists),
+                ] {
+                    if let Some(values) = values {
+                        results.push(
+                            generator
+                                .generate(
+                                    path,
+                                    config_path,
+                                    FullFilter::<StringOrVec, FilterOptions<bool>> {
+                                        localparts: values,
+                                        senders: None,
+                                        lists: None,
+                                        match_type: full_filter.match_type,
+                                        part: None,
+                                        priority,
+                                        labels: full_filter.labels.clone(),
+                                        options,
+                                    },
+                                )
+                                .map(|_| ()),
+                        );
+                    }
+                }
+                for result in results {
+                    self.errors(result);
+                }
+                let result = self
+                    .custom_filter_generator
+                    .generate(
+                        path,
+                        config_path,
+                        FullFilter::<StringOrVec, FilterOptions<bool>> {
+                            localparts: full_filter.localparts,
+                            senders: None,
+                            lists: None,
+                            match_type: full_filter.match_type,
+                            part: full_filter.part,
+                            priority,
+                            labels: full_filter.labels,
+                            options,
+                        },
+                    )
+                    .map(|_| ());
+                self.errors(result);
             }
             SieveDomainConfig::SubDomainConfig(mut sub_domain_configs) => {
                 if sub_domain_configs.is_empty() {
-                    panic!("ERROR: Found an empty sub-domain config, are you high ?");
+                    self.error(ErrorKind::EmptySubConfig, config_path);
                 }
 
                 let sub_domain_configs_len = sub_domain_configs.len();
-                for (sub, next_sub_config) in sub_domain_configs.drain() {
+                for (sub, next_sub_config) in sub_domain_configs.drain(..) {
+                    let next_config_path = [config_path, std::slice::from_ref(&sub)].concat();
                     if sub.is_empty() {
-                        panic!("ERROR: Oups...empty string cannot be used for folder name.");
+                        self.error(ErrorKind::EmptyFolderName, &next_config_path);
+                        continue;
                     }
                     let tmp: String;
                     let new_path = if path.is_empty() {
                         if sub == "self" {
-                            panic!("ERROR: Sorry baby ): 'self' field is not supported at domain level.");
+                            self.error(ErrorKind::SelfAtDomainLevel, &next_config_path);
+                            continue;
                         }
                         &sub
                     } else if sub == "self" {
                         if sub_domain_configs_len == 1 {
-                            panic!("ERROR: Hm...Why use 'self' if there is no sub-folder, are you high ?");
+                            self.error(ErrorKind::SelfWithoutSubFolder, &next_config_path);
                         }
                         /*
                          * If self field exist, generic filter generator for current path will be run again
@@ -118,42 +413,326 @@ impl DomainGenerator<'_> {
                         tmp = format!("{}/{}", path, sub);
                         &tmp
                     };
-                    self._generate(new_path, next_sub_config);
+                    self._generate(new_path, &next_config_path, next_sub_config);
                 }
             }
         }
 
         /* Generic filter, path is empty first recursive. */
         if options.generic && !path.is_empty() {
-            let prefix_generic_lps = path_to_prefix_generic_localpart(if !options.fullpath {
-                filename_of(path)
-            } else {
-                path
+            self.generics.push(GenericFilter {
+                path: path.to_string(),
+                config_path: config_path.to_vec(),
+                labels,
+                priority,
+                options,
             });
-            self.generic_filter_generator.generate(
-                path,
-                FullFilter::<StringOrVec, FilterOptions<bool>> {
-                    localparts: StringOrVec::Vec(vec![
-                        prefix_generic_lps.clone(),
-                        prefix_generic_lps + ".*",
-                    ]),
-                    labels,
-                    options,
-                },
-            );
         }
         self
     }
+
+    /*
+     * Custom filters come before generic ones, a wildcard in a custom localpart
+     * can take the mail of a generic filter and so on. Sender filters test another
+     * address, they aren't compared, nor are filters testing another part or other
+     * recipients.
+     */
+    fn lint_shadowing(&mut self) {
+        let mut groups = BTreeMap::<(Part, Recipients), Vec<shadowing::Branch>>::new();
+        for branch in self
+            .custom_filter_generator
+            .branches()
+            .into_iter()
+            .chain(self.generic_filter_generator.branches())
+        {
+            groups
+                .entry((branch.part, branch.recipients))
+                .or_default()
+                .push(branch);
+        }
+        let mut errors = vec![];
+        for branches in groups.values() {
+            /* generic filter of domain › Shopping */
+            let filter = |branch: &shadowing::Branch| {
+                format!(
+                    "{} filter of {}",
+                    branch.generator.to_lowercase(),
+                    location(&self.domain, branch.config_path)
+                )
+            };
+            errors.extend(shadowing::shadowed(branches).into_iter().map(|shadowed| {
+                let by = shadowed
+                    .earlier
+                    .iter()
+                    .map(|&i| format!("the {}", filter(&branches[i])))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let example = format!("{}@{}", shadowed.example, self.domain);
+                let later = filter(&branches[shadowed.later]);
+                (if shadowed.full {
+                    ErrorKind::ShadowedFilter(later, by, example)
+                } else {
+                    ErrorKind::PartlyShadowedFilter(later, by, example)
+                })
+                .at(&self.domain, branches[shadowed.later].config_path)
+            }));
+        }
+        self.errors(Err(errors));
+    }
+
+    /*
+     * Extensions the target doesn't support are reported at the folder using them,
+     * or at the domain for what is not specific to a folder.
+     */
+    fn check_target(&mut self) {
+        let Some(target) = self.target else {
+            return;
+        };
+        let mut errors = vec![];
+        /* first.last would be the folder last under first, not a folder per localpart. */
+        if self.unknown_by_localpart && self.profile.separator == '.' {
+            errors.push(
+                ErrorKind::InvalidOptionValue(
+                    "unknown-by-localpart".to_string(),
+                    "false on a target whose folder separator is \".\"",
+                )
+                .at(&self.domain, &["options", "unknown-by-localpart"]),
+            );
+        }
+        for generator in [
+            &self.sender_filter_generator,
+            &self.list_filter_generator,
+            &self.custom_filter_generator,
+            &self.generic_filter_generator,
+        ] {
+            for (branch, filter) in generator.to_sieve().into_iter().zip(generator.branches()) {
+                for capability in
+                    sieve::requires(&[sieve::Command::If(sieve::If::new(vec![branch], None))])
+                {
+                    if !self.profile.supports(&capability) {
+                        errors.push(
+                            ErrorKind::UnsupportedCapability(capability, target)
+                                .at(&self.domain, filter.config_path),
+                        );
+                    }
+                }
+            }
+        }
+        for capability in sieve::requires(&[self.to_sieve()]) {
+            if !self.profile.supports(&capability)
+                && !errors.iter().any(|e: &GenerateError| {
+                    e.kind == ErrorKind::UnsupportedCapability(capability.clone(), target)
+                })
+            {
+                errors.push(
+                    ErrorKind::UnsupportedCapability(capability, target)
+                        .at::<&str>(&self.domain, &[]),
+                );
+            }
+        }
+        self.errors(Err(errors));
+    }
+
+    /*
+     * A/Bills and B/Bills both get "bills" generic localparts, only the first filter would
+     * get the mail. With auto-fullpath the colliding folders use their full path instead,
+     * and say so in the output. Whatever still collides is reported.
+     */
+    fn generate_generics(&mut self) {
+        let mut generics = std::mem::take(&mut self.generics);
+        if self.auto_fullpath {
+            for colliding in generic_collisions(&generics) {
+                for &i in &colliding {
+                    if generics[i].options.fullpath || !generics[i].path.contains('/') {
+                        continue;
+                    }
+                    let others = colliding
+                        .iter()
+                        .filter(|&&j| j != i)
+                        .map(|&j| generics[j].path.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    let note = format!(
+                        "fullpath generic localparts, \"{}\" is also the folder name of {}",
+                        generics[i].localpart(),
+                        others
+                    );
+                    generics[i].options.fullpath = true;
+                    self.generic_filter_generator.note(&generics[i].path, note);
+                }
+            }
+        }
+        for colliding in generic_collisions(&generics) {
+            let first = &generics[colliding[0]];
+            let other = location(&self.domain, &first.config_path);
+            for &i in &colliding[1..] {
+                self.error(
+                    ErrorKind::GenericCollision(first.localpart(), other.clone()),
+                    &generics[i].config_path.clone(),
+                );
+            }
+        }
+
+        for generic in generics {
+            let prefix_generic_lps = escape_glob(&generic.localpart());
+            let mut localparts = vec![
+                prefix_generic_lps.clone(),
+                prefix_generic_lps.clone() + ".*",
+            ];
+            if generic.options.subaddress {
+                localparts.push(prefix_generic_lps + "+*");
+            }
+            let result = self
+                .generic_filter_generator
+                .generate(
+                    &generic.path,
+                    &generic.config_path,
+                    FullFilter::<StringOrVec, FilterOptions<bool>> {
+                        localparts: StringOrVec::Vec(localparts),
+                        senders: None,
+                        lists: None,
+                        match_type: Some(MatchType::Matches),
+                        part: None,
+                        priority: generic.priority,
+                        labels: generic.labels,
+                        options: generic.options,
+                    },
+                )
+                .map(|_| ());
+            self.errors(result);
+        }
+    }
+}
+
+/*
+ * A folder's generic filter, kept until every folder is known.
+ */
+struct GenericFilter {
+    path: String,
+    config_path: Vec<String>,
+    labels: Option<BTreeMap<String, StringOrVec>>,
+    priority: Option<i64>,
+    options: FilterOptions<bool>,
+}
+
+impl GenericFilter {
+    fn localpart(&self) -> String {
+        path_to_prefix_generic_localpart(if !self.options.fullpath {
+            filename_of(&self.path)
+        } else {
+            &self.path
+        })
+    }
+}
+
+/*
+ * Indexes of the generic filters sharing the same localpart, sorted by config path.
+ */
+fn generic_collisions(generics: &[GenericFilter]) -> Vec<Vec<usize>> {
+    let mut by_localpart = BTreeMap::<String, Vec<usize>>::new();
+    for (i, generic) in generics.iter().enumerate() {
+        by_localpart.entry(generic.localpart()).or_default().push(i);
+    }
+    by_localpart
+        .into_values()
+        .filter(|colliding| colliding.len() > 1)
+        .map(|mut colliding| {
+            colliding.sort_by(|&a, &b| generics[a].config_path.cmp(&generics[b].config_path));
+            colliding
+        })
+        .collect()
+}
+
+impl DomainGenerator<'_> {
+    /*
+     * Sender, list, custom then generic filters, whatever they don't catch is Unknown.
+     * Sender and list filters come first so a sender or a list gets into its folder
+     * whatever address it wrote to. The lists-folder branch is only a fallback, a list
+     * written to an address of a folder still gets into that folder.
+     */
+    pub fn to_sieve(&self) -> sieve::Command {
+        sieve::Command::If(sieve::If::new(
+            self.sender_filter_generator
+                .to_sieve()
+                .into_iter()
+                .chain(self.list_filter_generator.to_sieve())
+                .chain(self.custom_filter_generator.to_sieve())
+                .chain(self.generic_filter_generator.to_sieve())
+                .chain(self.lists_folder_branch())
+                .collect(),
+            Some(self.unknown()),
+        ))
+    }
+
+    /*
+     * The unknown domain option, by default marked as read and into Unknown. With
+     * unknown-by-localpart, a folder per localpart created on the first mail, so
+     * new aliases are grouped until they are added to the config.
+     */
+    fn unknown(&self) -> Vec<sieve::Command> {
+        let mut commands = vec![];
+        if self.unknown_mark_as_read
+            && matches!(self.unknown, CatchAll::Keep | CatchAll::FileInto(_))
+        {
+            commands.push(sieve::Command::AddFlag("\\Seen".to_string()));
+        }
+        commands.push(match &self.unknown {
+            CatchAll::Keep => sieve::Command::Keep,
+            CatchAll::FileInto(folder) if self.unknown_by_localpart => {
+                sieve::Command::If(sieve::If::new(
+                    vec![sieve::Branch::new(
+                        self.profile.recipient(
+                            sieve::AddressPart::Localpart,
+                            sieve::MatchType::Matches,
+                            "*".into(),
+                            self.recipients,
+                        ),
+                        vec![sieve::Command::FileIntoCreate(
+                            self.profile.folder(&format!("{}/${{1}}", folder)),
+                        )],
+                    )],
+                    Some(vec![sieve::Command::FileInto(self.profile.folder(folder))]),
+                ))
+            }
+            CatchAll::FileInto(folder) => sieve::Command::FileInto(self.profile.folder(folder)),
+            CatchAll::Reject(message) => sieve::Command::Reject(message.clone()),
+            CatchAll::Discard => sieve::Command::Discard,
+            CatchAll::Redirect(address) => sieve::Command::Redirect(address.clone()),
+        });
+        commands
+    }
+
+    /*
+     * The lists no filter took go to a sub-folder named after the label of their
+     * List-Id, "Rust users <rust-users.lists.rust-lang.org>" -> Lists/rust-users.
+     * Wildcards match as little as they can, ${2} stops at the first dot.
+     * A list's folder doesn't exist before its first mail, it is created then.
+     */
+    fn lists_folder_branch(&self) -> Option<sieve::Branch> {
+        let lists_folder = self.lists_folder.as_ref()?;
+        let mut commands = self
+            .list_filter_generator
+            .file_into(&format!("{}/${{2}}", lists_folder));
+        if let Some(sieve::Command::FileInto(folder)) = commands.pop() {
+            commands.push(sieve::Command::FileIntoCreate(folder));
+        }
+        Some(
+            sieve::Branch::new(
+                sieve::Test::Header {
+                    match_type: sieve::MatchType::Matches,
+                    headers: "list-id".into(),
+                    keys: "*<*.*>*".into(),
+                },
+                commands,
+            )
+            .with_comment("List-Id label filters"),
+        )
+    }
 }
 
 impl fmt::Display for DomainGenerator<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            self.custom_filter_generator.to_string()
-                + &self.generic_filter_generator.to_string_with_unknown()
-        )
+        write!(f, "{}", sieve::render(&[self.to_sieve()]))
     }
 }
 
@@ -161,10 +740,10 @@ impl fmt::Display for DomainGenerator<'_> {
  * "A B"/C -> a-b.c
  */
 fn path_to_prefix_generic_localpart(path: &str) -> String {
-    Regex::new(r"/")
-        .unwrap()
-        .replace_all(&Regex::new(r"\s+").unwrap().replace_all(path, "-"), ".")
-        .into_owned()
+    static WHITESPACES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s+").unwrap());
+    WHITESPACES
+        .replace_all(path, "-")
+        .replace('/', ".")
         .to_lowercase()
 }
 
@@ -172,17 +751,7 @@ fn path_to_prefix_generic_localpart(path: &str) -> String {
  * A/B/C -> C
  */
 fn filename_of(path: &str) -> &str {
-    if path.contains('/') {
-        Regex::new(r".*/(.+)$")
-            .unwrap()
-            .captures(path)
-            .unwrap()
-            .get(1)
-            .unwrap()
-            .as_str()
-    } else {
-        path
-    }
+    path.rsplit('/').next().unwrap_or(path)
 }
 
 #[cfg(test)]
@@ -203,7 +772,7 @@ mod tests {
 
     #[test]
     fn domain_generator() {
-        let mut g = super::DomainGenerator::new("domain", false);
+        let mut g = super::DomainGenerator::new("domain", &Default::default());
         g.generate(
             serde_json::from_str::<super::SieveDomainConfig>(
                 r#"
@@ -247,7 +816,8 @@ mod tests {
                     }"#,
             )
             .unwrap(),
-        );
+        )
+        .unwrap();
         assert_eq!(
             g.to_string(),
             r#"
@@ -311,7 +881,13 @@ elsif envelope :localpart :matches "to" ["grocery","grocery.*"] {
 
     #[test]
     fn domain_generator_domain_as_first_folder() {
-        let mut g = super::DomainGenerator::new("domain", true);
+        let mut g = super::DomainGenerator::new(
+            "domain",
+            &super::DomainOptions {
+                domain_as_first_folder: true,
+                ..Default::default()
+            },
+        );
         g.generate(
             serde_json::from_str::<super::SieveDomainConfig>(
                 r#"
@@ -322,7 +898,8 @@ elsif envelope :localpart :matches "to" ["grocery","grocery.*"] {
                         }"#,
             )
             .unwrap(),
-        );
+        )
+        .unwrap();
         assert_eq!(
             g.to_string(),
             r#"
@@ -344,37 +921,837 @@ elsif envelope :localpart :matches "to" ["business","business.*"] {
         );
     }
 
+    fn generate_errors(config: &str) -> Vec<super::GenerateError> {
+        match super::DomainGenerator::new("domain", &Default::default())
+            .generate(serde_json::from_str::<super::SieveDomainConfig>(config).unwrap())
+        {
+            Ok(_) => panic!("{} should not generate", config),
+            Err(errors) => errors,
+        }
+    }
+
+    fn generate_warnings(config: &str) -> Vec<String> {
+        let mut warnings = super::DomainGenerator::new("domain", &Default::default())
+            .generate(serde_json::from_str::<super::SieveDomainConfig>(config).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|e| format!("{} {}", e.code(), e.location()))
+            .collect::<Vec<_>>();
+        warnings.sort();
+        warnings
+    }
+
+    fn generate_error(config: &str) -> super::GenerateError {
+        let mut errors = generate_errors(config);
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        errors.remove(0)
+    }
+
+    #[test]
+    fn domain_generator_error_empty_config() {
+        let e = generate_error(r#"{"folder": {} }"#);
+        assert_eq!(e.code(), "E0006");
+        assert_eq!(e.location(), "domain › folder");
+    }
+
+    #[test]
+    fn domain_generator_error_self_domain() {
+        assert_eq!(generate_error(r#"{"self": "self"}"#).code(), "E0008");
+    }
+
+    #[test]
+    fn domain_generator_error_self_with_no_sub() {
+        let e = generate_error(r#"{"folder": { "self": "" } }"#);
+        assert_eq!(e.code(), "E0009");
+        assert_eq!(e.location(), "domain › folder › self");
+    }
+
+    #[test]
+    fn domain_generator_error_folder_cannot_be_empty_string() {
+        assert_eq!(
+            generate_error(r#"{"folder1": { "": "" } }"#).code(),
+            "E0007"
+        );
+    }
+
+    #[test]
+    fn domain_generator_error_useless_fullpath() {
+        let e = generate_error(
+            r#"{"folder": { "localparts": "a", "options": { "fullpath": true } } }"#,
+        );
+        assert_eq!(e.code(), "E0011");
+        assert_eq!(e.location(), "domain › folder › options › fullpath");
+        assert_eq!(
+            generate_error(
+                r#"{"A": { "B": { "localparts": "a", "options": { "fullpath": true, "generic": false } } } }"#,
+            )
+            .code(),
+            "E0010"
+        );
+    }
+
+    #[test]
+    fn domain_generator_error_empty_label_keywords() {
+        let e = generate_error(
+            r#"{"Utilities": { "Grocery": { "localparts": "market", "labels": { "label": [] } } } }"#,
+        );
+        assert_eq!(e.code(), "E0013");
+        assert_eq!(
+            e.location(),
+            "domain › Utilities › Grocery › labels › label"
+        );
+    }
+
+    #[test]
+    fn domain_generator_collect_all_errors() {
+        let mut errors = generate_errors(
+            r#"
+            {
+                "self": "a",
+                "Empty": {},
+                "Newsletter": {
+                    "": "b",
+                    "Software": ["google", ""],
+                    "Business": {
+                        "localparts": [],
+                        "labels": { "": "keyword", "label": [] }
+                    }
+                },
+                "Utilities": {
+                    "self": {
+                        "localparts": "c",
+                        "options": { "generic": false, "fullpath": true }
+                    }
+                }
+            }"#,
+        )
+        .into_iter()
+        .map(|e| format!("{} {}", e.code(), e.location()))
+        .collect::<Vec<_>>();
+        errors.sort();
+        assert_eq!(
+            errors,
+            [
+                "E0006 domain › Empty",
+                "E0007 domain › Newsletter › \"\"",
+                "E0008 domain › self",
+                "E0009 domain › Utilities › self",
+                "E0010 domain › Utilities › self › options › fullpath",
+                "E0012 domain › Newsletter › Business › labels",
+                "E0013 domain › Newsletter › Business › labels › label",
+                "E0013 domain › Newsletter › Business › localparts",
+                "E0014 domain › Newsletter › Software › localparts",
+            ]
+        );
+    }
+
     #[test]
-    #[should_panic(expected = "are you high")]
-    fn domain_generator_panic_empty_config() {
-        super::DomainGenerator::new("domain", false).generate(
-            serde_json::from_str::<super::SieveDomainConfig>(r#"{"folder": {} }"#).unwrap(),
+    fn domain_generator_lints() {
+        assert_eq!(
+            generate_warnings(
+                r#"
+                {
+                    "Newsletter": {
+                        "Software": ["google", "facebook", "google"],
+                        "Business": { "localparts": "", "options": { "generic": false } }
+                    },
+                    "Unknown": "",
+                    "Trash": { "localparts": "", "options": { "generic": false } }
+                }"#
+            ),
+            [
+                "L0001 domain › Newsletter › Business",
+                "L0001 domain › Trash",
+                "L0002 domain › Newsletter › Software",
+            ]
+        );
+    }
+
+    #[test]
+    fn domain_generator_lint_localpart_claimed_twice() {
+        let config = r#"
+            {
+                "Newsletter": { "Software": ["google", "facebook"] },
+                "Utilities": { "self": "Google", "Bill": { "localparts": "facebook" } }
+            }"#;
+        /* Utilities/Bill and Utilities come before Newsletter/Software in the script. */
+        assert_eq!(
+            generate_warnings(config),
+            [
+                "L0004 domain › Newsletter › Software",
+                "L0004 domain › Newsletter › Software",
+            ]
+        );
+        let errors = match super::DomainGenerator::new(
+            "domain",
+            &super::DomainOptions {
+                duplicate_localparts: super::Severity::Error,
+                ..Default::default()
+            },
+        )
+        .generate(serde_json::from_str::<super::SieveDomainConfig>(config).unwrap())
+        {
+            Ok(_) => panic!("duplicate localparts should be errors"),
+            Err(errors) => errors,
+        };
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().all(super::GenerateError::is_error));
+        assert!(errors.iter().any(|e| e.to_string()
+            == "error[L0004]: localpart \"google\" is already claimed by domain › Utilities › self, only one of them will get the mail\n  --> domain › Newsletter › Software"));
+
+        /* A priority moves the filter, and the claim, first. */
+        let config = r#"
+            {
+                "Newsletter": { "Software": { "localparts": "google", "priority": 1 } },
+                "Utilities": "google"
+            }"#;
+        let warnings = super::DomainGenerator::new("domain", &Default::default())
+            .generate(serde_json::from_str::<super::SieveDomainConfig>(config).unwrap())
+            .unwrap();
+        assert_eq!(
+            warnings.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            ["warning[L0004]: localpart \"google\" is already claimed by domain › Newsletter › Software, only one of them will get the mail\n  --> domain › Utilities"]
         );
     }
 
     #[test]
-    #[should_panic(expected = "'self' field is not supported at domain level")]
-    fn domain_generator_panic_self_domain() {
-        super::DomainGenerator::new("domain", false).generate(
-            serde_json::from_str::<super::SieveDomainConfig>(r#"{"self": "self"}"#).unwrap(),
+    fn domain_generator_lint_generic_collision() {
+        assert_eq!(
+            generate_warnings(
+                r#"
+                {
+                    "Home": { "self": "home", "Bills": "electricity" },
+                    "Work": { "self": "work", "Bills": "expenses" },
+                    "Bills": "bills"
+                }"#
+            ),
+            [
+                "L0005 domain › Home › Bills",
+                "L0005 domain › Work › Bills",
+                /* bills@ is Bills's custom localpart, which comes first. */
+                "L0006 domain › Home › Bills",
+                "L0007 domain › Work › Bills",
+            ]
         );
     }
 
     #[test]
-    #[should_panic(expected = "are you high")]
-    fn domain_generator_panic_self_with_no_sub() {
-        super::DomainGenerator::new("domain", false).generate(
-            serde_json::from_str::<super::SieveDomainConfig>(r#"{"folder": { "self": "" } }"#)
+    fn domain_generator_auto_fullpath() {
+        let mut g = super::DomainGenerator::new(
+            "domain",
+            &super::DomainOptions {
+                auto_fullpath: true,
+                ..Default::default()
+            },
+        );
+        assert!(g
+            .generate(
+                serde_json::from_str::<super::SieveDomainConfig>(
+                    r#"
+                    {
+                        "Home": { "Bills": "electricity" },
+                        "Work": { "Bills": "expenses" }
+                    }"#,
+                )
                 .unwrap(),
+            )
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            g.to_string(),
+            r#"
+# Custom filters
+if envelope :localpart :matches "to" ["expenses"] {
+    fileinto "Work";
+    fileinto "Work/Bills";
+} elsif envelope :localpart :matches "to" ["electricity"] {
+    fileinto "Home";
+    fileinto "Home/Bills";
+}
+# Generic filters
+elsif envelope :localpart :matches "to" ["work.bills","work.bills.*"] {
+    # fullpath generic localparts, "bills" is also the folder name of Home/Bills
+    fileinto "Work";
+    fileinto "Work/Bills";
+} elsif envelope :localpart :matches "to" ["work","work.*"] {
+    fileinto "Work";
+} elsif envelope :localpart :matches "to" ["home.bills","home.bills.*"] {
+    # fullpath generic localparts, "bills" is also the folder name of Work/Bills
+    fileinto "Home";
+    fileinto "Home/Bills";
+} elsif envelope :localpart :matches "to" ["home","home.*"] {
+    fileinto "Home";
+} else {
+    addflag "\\Seen";
+    fileinto "Unknown";
+}"#
         );
     }
 
     #[test]
-    #[should_panic(expected = "empty string cannot be used")]
-    fn domain_generator_panic_folder_cannot_be_empty_string() {
-        super::DomainGenerator::new("domain", false).generate(
-            serde_json::from_str::<super::SieveDomainConfig>(r#"{"folder1": { "": "" } }"#)
+    fn domain_generator_lint_shadowing() {
+        let config = r#"
+            {
+                "News": { "localparts": "news*", "match": "matches" },
+                "Newsletter": { "localparts": "", "options": { "generic": true } },
+                "Shop": { "localparts": "shop*", "match": "matches" },
+                "Shopping": ["shopping", "bills"]
+            }"#;
+        assert_eq!(
+            generate_warnings(config),
+            [
+                "L0006 domain › Newsletter",
+                "L0006 domain › Shopping",
+                "L0007 domain › Shop",
+            ]
+        );
+        let warnings = super::DomainGenerator::new("domain", &Default::default())
+            .generate(serde_json::from_str::<super::SieveDomainConfig>(config).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        assert!(warnings.contains(
+            &"warning[L0006]: generic filter of domain › Newsletter is never reached, every address it matches is taken by the custom filter of domain › News first, e.g. newsletter@domain\n  --> domain › Newsletter".to_string()
+        ), "{:#?}", warnings);
+        assert!(warnings.contains(
+            &"warning[L0007]: some addresses of the custom filter of domain › Shop are taken by the custom filter of domain › Shopping first, e.g. shopping@domain\n  --> domain › Shop".to_string()
+        ), "{:#?}", warnings);
+
+        /* The very same localpart in a custom and a generic filter. */
+        assert_eq!(
+            generate_warnings(r#"{ "Foo": "newsletter", "Newsletter": "wallstreet" }"#),
+            ["L0007 domain › Newsletter"]
+        );
+        assert_eq!(
+            generate_warnings(
+                r#"{ "Foo": { "localparts": "bills.*", "match": "matches" }, "Bills": "invoice" }"#
+            ),
+            ["L0007 domain › Bills"]
+        );
+    }
+
+    #[test]
+    fn domain_generator_priority() {
+        let config = r#"
+            {
+                "Bank": { "localparts": "bank", "priority": -1 },
+                "Shop": { "localparts": "shop*", "match": "matches", "priority": 1 },
+                "Shopping": ["shopping", "bills"],
+                "Work": "boss"
+            }"#;
+        let mut g = super::DomainGenerator::new("domain", &Default::default());
+        let warnings = g
+            .generate(serde_json::from_str::<super::SieveDomainConfig>(config).unwrap())
+            .unwrap();
+        assert_eq!(
+            g.custom_filter_generator
+                .branches()
+                .iter()
+                .map(|branch| branch.path)
+                .collect::<Vec<_>>(),
+            ["Shop", "Work", "Shopping", "Bank"]
+        );
+        /* The lints follow the order of the script. */
+        assert_eq!(
+            warnings
+                .iter()
+                .map(|e| format!("{} {}", e.code(), e.location()))
+                .collect::<Vec<_>>(),
+            ["L0007 domain › Shopping", "L0006 domain › Shopping"]
+        );
+        /* Same folder twice, the messages tell its branches apart. */
+        assert!(warnings[0]
+            .to_string()
+            .starts_with("warning[L0007]: some addresses of the custom filter of domain › Shopping are taken by the custom filter of domain › Shop first"));
+        assert!(warnings[1]
+            .to_string()
+            .starts_with("warning[L0006]: generic filter of domain › Shopping is never reached"));
+    }
+
+    #[test]
+    fn domain_generator_senders() {
+        let mut g = super::DomainGenerator::new("domain", &Default::default());
+        let warnings = g
+            .generate(
+                serde_json::from_str::<super::SieveDomainConfig>(
+                    r#"
+                {
+                    "Dev": {
+                        "GitHub": {
+                            "localparts": "",
+                            "senders": "github.com",
+                            "options": { "generic": false }
+                        },
+                        "CI": {
+                            "localparts": "ci",
+                            "senders": ["builds@travis-ci.com", "*.circleci.com"],
+                            "match": "matches"
+                        }
+                    },
+                    "Work": "boss"
+                }"#,
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        /* Senders first, whatever the recipient, then custom and generic localparts. */
+        assert_eq!(
+            g.to_string(),
+            r#"
+# Sender filters
+if address :domain :matches "from" ["github.com"] {
+    fileinto "Dev";
+    fileinto "Dev/GitHub";
+} elsif anyof(address :domain :matches "from" ["*.circleci.com"], address :matches "from" ["builds@travis-ci.com"]) {
+    fileinto "Dev";
+    fileinto "Dev/CI";
+}
+# Custom filters
+elsif envelope :localpart :matches "to" ["boss"] {
+    fileinto "Work";
+} elsif envelope :localpart :matches "to" ["ci"] {
+    fileinto "Dev";
+    fileinto "Dev/CI";
+}
+# Generic filters
+elsif envelope :localpart :matches "to" ["work","work.*"] {
+    fileinto "Work";
+} elsif envelope :localpart :matches "to" ["ci","ci.*"] {
+    fileinto "Dev";
+    fileinto "Dev/CI";
+} elsif envelope :localpart :matches "to" ["dev","dev.*"] {
+    fileinto "Dev";
+} else {
+    addflag "\\Seen";
+    fileinto "Unknown";
+}"#
+        );
+
+        /* Without match, *@ and *. are still wildcards, anything else is literal. */
+        let mut g = super::DomainGenerator::new("domain", &Default::default());
+        g.generate(
+            serde_json::from_str::<super::SieveDomainConfig>(
+                r#"{ "GitHub": { "localparts": "", "senders": ["*@github.com", "*.github.io", "a*b@x.com"], "options": { "generic": false } } }"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            g.to_string(),
+            r#"
+# Sender filters
+if anyof(address :domain :matches "from" ["*.github.io"], address :matches "from" ["*@github.com","a\\*b@x.com"]) {
+    fileinto "GitHub";
+} else {
+    addflag "\\Seen";
+    fileinto "Unknown";
+}"#
+        );
+
+        let mut g = super::DomainGenerator::new("domain", &Default::default());
+        let errors = g
+            .generate(
+                serde_json::from_str::<super::SieveDomainConfig>(
+                    r#"{ "GitHub": { "localparts": "", "senders": [] } }"#,
+                )
+                .unwrap(),
+            )
+            .unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "error[E0013]: array of senders cannot be empty\n  --> domain › GitHub › senders"
+        );
+    }
+
+    #[test]
+    fn domain_generator_lists() {
+        let options = super::DomainOptions {
+            domain_as_first_folder: true,
+            lists_folder: Some("Newsletter/Lists".to_string()),
+            ..Default::default()
+        };
+        let mut g = super::DomainGenerator::new("domain.com", &options);
+        g.generate(
+            serde_json::from_str::<super::SieveDomainConfig>(
+                r#"
+                {
+                    "Rust": {
+                        "localparts": "",
+                        "lists": ["rust-users.lists.rust-lang.org", "rust-dev"],
+                        "options": { "generic": false }
+                    },
+                    "Dev": { "localparts": "dev", "senders": "github.com", "lists": "dev.example.org" }
+                }"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert!(super::sieve::requires(&[g.to_sieve()]).contains("variables"));
+        /* Any list written to dev@ gets into Dev, only the others into their label folder. */
+        assert_eq!(
+            g.to_string(),
+            r#"
+# Sender filters
+if address :domain :matches "from" ["github.com"] {
+    fileinto "@domain.com/Dev";
+}
+# List filters
+elsif header :contains "list-id" ["rust-users.lists.rust-lang.org","rust-dev"] {
+    fileinto "@domain.com/Rust";
+} elsif header :contains "list-id" ["dev.example.org"] {
+    fileinto "@domain.com/Dev";
+}
+# Custom filters
+elsif envelope :localpart :matches "to" ["dev"] {
+    fileinto "@domain.com/Dev";
+}
+# Generic filters
+elsif envelope :localpart :matches "to" ["dev","dev.*"] {
+    fileinto "@domain.com/Dev";
+}
+# List-Id label filters
+elsif header :matches "list-id" "*<*.*>*" {
+    fileinto "@domain.com/Newsletter";
+    fileinto "@domain.com/Newsletter/Lists";
+    fileinto :create "@domain.com/Newsletter/Lists/${2}";
+} else {
+    addflag "\\Seen";
+    fileinto "Unknown";
+}"#
+        );
+    }
+
+    #[test]
+    fn domain_generator_subaddress() {
+        let mut g = super::DomainGenerator::new("domain", &Default::default());
+        let warnings = g
+            .generate(
+                serde_json::from_str::<super::SieveDomainConfig>(
+                    r#"
+                {
+                    "Shopping": {
+                        "localparts": "shop",
+                        "part": "user",
+                        "options": { "subaddress": true, "detail-folder": true }
+                    },
+                    "Amazon": { "localparts": "amazon", "part": "detail", "options": { "generic": false } },
+                    "Work": { "localparts": "shop", "options": { "generic": false } }
+                }"#,
+                )
                 .unwrap(),
+            )
+            .unwrap();
+        /* shop is claimed twice, +amazon isn't shop. */
+        assert_eq!(
+            warnings
+                .iter()
+                .map(|e| format!("{} {}", e.code(), e.location()))
+                .collect::<Vec<_>>(),
+            ["L0004 domain › Shopping"]
+        );
+        assert!(super::sieve::requires(&[g.to_sieve()]).contains("subaddress"));
+        assert!(super::sieve::requires(&[g.to_sieve()]).contains("variables"));
+        assert_eq!(
+            g.to_string(),
+            r#"
+# Custom filters
+if envelope :localpart :matches "to" ["shop"] {
+    fileinto "Work";
+} elsif envelope :user :matches "to" ["shop"] {
+    fileinto "Shopping";
+    if envelope :detail :matches "to" "?*" {
+        fileinto :create "Shopping/${1}${2}";
+    }
+} elsif envelope :detail :matches "to" ["amazon"] {
+    fileinto "Amazon";
+}
+# Generic filters
+elsif envelope :localpart :matches "to" ["shopping","shopping.*","shopping+*"] {
+    fileinto "Shopping";
+    if envelope :detail :matches "to" "?*" {
+        fileinto :create "Shopping/${1}${2}";
+    }
+} else {
+    addflag "\\Seen";
+    fileinto "Unknown";
+}"#
+        );
+    }
+
+    #[test]
+    fn domain_generator_match() {
+        let mut g = super::DomainGenerator::new("domain", &Default::default());
+        g.generate(
+            serde_json::from_str::<super::SieveDomainConfig>(
+                r#"
+                {
+                    "A": { "localparts": ["what?", "a*b\\c"], "options": { "generic": false } },
+                    "B": { "localparts": "b", "match": "is", "options": { "generic": false } },
+                    "C": { "localparts": "c*", "match": "matches", "options": { "generic": false } },
+                    "D*": { "localparts": "^d[0-9]+$", "match": "regex" }
+                }"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert!(super::sieve::requires(&[g.to_sieve()]).contains("regex"));
+        assert_eq!(
+            g.to_string(),
+            r#"
+# Custom filters
+if envelope :localpart :regex "to" ["^d[0-9]+$"] {
+    fileinto "D*";
+} elsif envelope :localpart :matches "to" ["c*"] {
+    fileinto "C";
+} elsif envelope :localpart :is "to" ["b"] {
+    fileinto "B";
+} elsif envelope :localpart :matches "to" ["what\\?","a\\*b\\\\c"] {
+    fileinto "A";
+}
+# Generic filters
+elsif envelope :localpart :matches "to" ["d\\*","d\\*.*"] {
+    fileinto "D*";
+} else {
+    addflag "\\Seen";
+    fileinto "Unknown";
+}"#
+        );
+    }
+
+    #[test]
+    fn domain_generator_sieve_strings() {
+        let mut g = super::DomainGenerator::new("domain", &Default::default());
+        g.generate(
+            serde_json::from_str::<super::SieveDomainConfig>(
+                r#"
+                {
+                    "Café \"Bar\"": {
+                        "localparts": "café",
+                        "labels": { "Réunion\\Team": ["été", "a\nb"] },
+                        "options": { "generic": false }
+                    }
+                }"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            g.to_string(),
+            r#"
+# Custom filters
+if envelope :localpart :matches "to" ["café"] {
+    fileinto "Café \"Bar\"";
+    if header :contains ["from","subject"] ["été",text:
+a
+b
+.
+    ] {
+        fileinto "Réunion\\Team";
+    }
+} else {
+    addflag "\\Seen";
+    fileinto "Unknown";
+}"#
+        );
+    }
+
+    #[test]
+    fn domain_generator_unknown_by_localpart() {
+        let config = r#"{ "Work": { "localparts": "boss", "options": { "generic": false } } }"#;
+        let options = super::DomainOptions {
+            unknown_by_localpart: true,
+            target: Some(super::Target::Pigeonhole),
+            ..Default::default()
+        };
+        let mut g = super::DomainGenerator::new("domain", &options);
+        g.generate(serde_json::from_str::<super::SieveDomainConfig>(config).unwrap())
+            .unwrap();
+        assert_eq!(
+            super::sieve::requires(&[g.to_sieve()])
+                .into_iter()
+                .collect::<Vec<_>>(),
+            ["envelope", "fileinto", "imap4flags", "mailbox", "variables"]
+        );
+        assert_eq!(
+            g.to_string(),
+            r#"
+# Custom filters
+if envelope :localpart :matches "to" ["boss"] {
+    fileinto "Work";
+} else {
+    addflag "\\Seen";
+    if envelope :localpart :matches "to" "*" {
+        fileinto :create "Unknown/${1}";
+    } else {
+        fileinto "Unknown";
+    }
+}"#
+        );
+
+        let errors = super::DomainGenerator::new(
+            "domain",
+            &super::DomainOptions {
+                target: Some(super::Target::Proton),
+                ..options.clone()
+            },
+        )
+        .generate(serde_json::from_str::<super::SieveDomainConfig>(config).unwrap())
+        .unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            ["error[E0020]: Proton does not support the \"mailbox\" extension\n  --> domain"]
+        );
+
+        let errors = super::DomainGenerator::new(
+            "domain",
+            &super::DomainOptions {
+                target: Some(super::Target::Fastmail),
+                ..options
+            },
+        )
+        .generate(serde_json::from_str::<super::SieveDomainConfig>(config).unwrap())
+        .unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            ["error[E0019]: unknown-by-localpart option must be false on a target whose folder separator is \".\"\n  --> domain › options › unknown-by-localpart"]
+        );
+    }
+
+    #[test]
+    fn domain_generator_catch_all() {
+        /* The configured folder is the special one, Unknown is an ordinary folder. */
+        let config = r#"{ "Unknown": "x", "Spam": { "Old": "old" } }"#;
+        let mut g = super::DomainGenerator::new(
+            "domain",
+            &super::DomainOptions {
+                unknown: super::CatchAll::FileInto("Spam".to_string()),
+                ..Default::default()
+            },
+        );
+        g.generate(serde_json::from_str::<super::SieveDomainConfig>(config).unwrap())
+            .unwrap();
+        assert_eq!(
+            g.to_string(),
+            r#"
+# Custom filters
+if envelope :localpart :matches "to" ["x"] {
+    fileinto "Unknown";
+} elsif envelope :localpart :matches "to" ["old"] {
+    fileinto "Spam";
+    fileinto "Spam/Old";
+    addflag "\\Seen";
+}
+# Generic filters
+elsif envelope :localpart :matches "to" ["unknown","unknown.*"] {
+    fileinto "Unknown";
+} else {
+    addflag "\\Seen";
+    fileinto "Spam";
+}"#
+        );
+
+        let config = r#"{ "Work": { "localparts": "boss", "options": { "generic": false } } }"#;
+        for (unknown, mark_as_read, otherwise) in [
+            (
+                super::CatchAll::Keep,
+                true,
+                "addflag \"\\\\Seen\";\n    keep;",
+            ),
+            (super::CatchAll::Keep, false, "keep;"),
+            (
+                super::CatchAll::FileInto("Spam".to_string()),
+                false,
+                "fileinto \"Spam\";",
+            ),
+            (
+                super::CatchAll::Reject("No such address.".to_string()),
+                true,
+                "reject \"No such address.\";",
+            ),
+            (super::CatchAll::Discard, true, "discard;"),
+            (
+                super::CatchAll::Redirect("me@other.com".to_string()),
+                true,
+                "redirect \"me@other.com\";",
+            ),
+        ] {
+            let mut g = super::DomainGenerator::new(
+                "domain",
+                &super::DomainOptions {
+                    unknown,
+                    unknown_mark_as_read: mark_as_read,
+                    ..Default::default()
+                },
+            );
+            g.generate(serde_json::from_str::<super::SieveDomainConfig>(config).unwrap())
+                .unwrap();
+            assert_eq!(
+                g.to_string(),
+                format!(
+                    "\n# Custom filters\nif envelope :localpart :matches \"to\" [\"boss\"] {{\n    fileinto \"Work\";\n}} else {{\n    {}\n}}",
+                    otherwise
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn domain_generator_target() {
+        let config = r#"
+            {
+                "Newsletter": {
+                    "Software": { "localparts": "^g[0-9]$", "match": "regex" },
+                    "Business": "wallstreet"
+                }
+            }"#;
+        let errors = match super::DomainGenerator::new(
+            "domain",
+            &super::DomainOptions {
+                target: Some(super::Target::Proton),
+                ..Default::default()
+            },
+        )
+        .generate(serde_json::from_str::<super::SieveDomainConfig>(config).unwrap())
+        {
+            Ok(_) => panic!("Proton has no regex"),
+            Err(errors) => errors,
+        };
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            ["error[E0020]: Proton does not support the \"regex\" extension\n  --> domain › Newsletter › Software"]
+        );
+
+        let mut g = super::DomainGenerator::new(
+            "domain",
+            &super::DomainOptions {
+                target: Some(super::Target::Fastmail),
+                ..Default::default()
+            },
+        );
+        g.generate(
+            serde_json::from_str::<super::SieveDomainConfig>(
+                r#"{ "Newsletter": { "Business": { "localparts": "wallstreet", "options": { "generic": false } } } }"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            g.to_string(),
+            r#"
+# Custom filters
+if envelope :localpart :matches "to" ["wallstreet"] {
+    fileinto "Newsletter";
+    fileinto "Newsletter.Business";
+}
+# Generic filters
+elsif envelope :localpart :matches "to" ["newsletter","newsletter.*"] {
+    fileinto "Newsletter";
+} else {
+    addflag "\\Seen";
+    fileinto "Unknown";
+}"#
         );
     }
 }
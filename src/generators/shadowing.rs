@@ -0,0 +1,375 @@
+use crate::common::{MatchType, Part, Recipients};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+
+/*
+ * One branch of the if/elsif chain, in the order of the generated script.
+ */
+#[derive(Debug)]
+pub struct Branch<'a> {
+    pub path: &'a str,
+    pub generator: &'a str, // Custom or Generic, the path alone doesn't tell them apart.
+    pub config_path: &'a [String],
+    pub localparts: &'a [String],
+    pub match_type: Option<MatchType>,
+    pub part: Part,
+    pub recipients: Recipients, // Only branches testing the same part of the same ones are compared.
+}
+
+/*
+ * Branch `later` gets none (full) or only some of the addresses it matches,
+ * the others are taken by the `earlier` branches, e.g. `example`.
+ */
+#[derive(Debug, PartialEq)]
+pub struct Shadowed {
+    pub later: usize,
+    pub earlier: Vec<usize>,
+    pub full: bool,
+    pub example: String,
+}
+
+impl Branch<'_> {
+    /*
+     * None for :regex, which is not modeled.
+     */
+    fn patterns(&self) -> Option<Vec<Vec<Token>>> {
+        let parse_localpart = |localpart: &String| match self.match_type {
+            Some(MatchType::Matches) => parse(localpart),
+            _ => localpart
+                .chars()
+                .map(|c| Token::Char(c.to_ascii_lowercase()))
+                .collect(),
+        };
+        match self.match_type {
+            Some(MatchType::Regex) => None,
+            _ => Some(self.localparts.iter().map(parse_localpart).collect()),
+        }
+    }
+}
+
+/*
+ * Compare every branch with the ones before it, using Sieve :is and :matches semantics.
+ * A branch of the same folder or of a sub-folder is expected to come first
+ * (A/B before A), it is not shadowing, even with the same localpart. Neither is
+ * a localpart or a generic filter listed twice, which is already reported on its own.
+ */
+pub fn shadowed(branches: &[Branch]) -> Vec<Shadowed> {
+    let all_patterns = branches.iter().map(Branch::patterns).collect::<Vec<_>>();
+    let mut index = Index::default();
+    let mut result = vec![];
+    for (later, branch) in branches.iter().enumerate() {
+        let Some(patterns) = &all_patterns[later] else {
+            continue;
+        };
+        let expected = |i: usize| {
+            let other = &branches[i];
+            other.path == branch.path || other.path.starts_with(&format!("{}/", branch.path))
+        };
+        /* The same pattern in another folder's other filter is the most direct shadowing. */
+        let new_patterns = patterns
+            .iter()
+            .filter(|pattern| {
+                index.seen.get(*pattern).is_none_or(|seen| {
+                    !seen
+                        .iter()
+                        .all(|&i| expected(i) || branches[i].generator == branch.generator)
+                })
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let candidates = index.candidates(&new_patterns);
+        index.insert(later, patterns);
+        let patterns = new_patterns;
+        if patterns.is_empty() {
+            continue;
+        }
+        let mut earlier = vec![];
+        let mut example = None;
+        for i in candidates {
+            if expected(i) {
+                continue;
+            }
+            let Some(others) = &all_patterns[i] else {
+                continue;
+            };
+            if let Some(both) = find(&patterns, others, |a, b| a && b) {
+                earlier.push(i);
+                example.get_or_insert(both);
+            }
+        }
+        let Some(example) = example else {
+            continue;
+        };
+        let union = earlier
+            .iter()
+            .flat_map(|&i| all_patterns[i].iter().flatten().cloned())
+            .collect::<Vec<_>>();
+        result.push(Shadowed {
+            later,
+            earlier,
+            full: find(&patterns, &union, |a, b| a && !b).is_none(),
+            example,
+        });
+    }
+    result
+}
+
+/*
+ * Patterns of the branches seen so far, by the literal text they start with.
+ * Two patterns can only match the same localpart if the prefix of one of them
+ * is a prefix of the other, and two literals only if they are the same.
+ * This keeps the candidates of a branch to a few among tens of thousands of
+ * localparts.
+ */
+#[derive(Default)]
+struct Index {
+    seen: HashMap<Vec<Token>, Vec<usize>>, // Branches with this very pattern.
+    literals: BTreeMap<String, Vec<usize>>,
+    globs: BTreeMap<String, Vec<usize>>,
+}
+
+impl Index {
+    fn insert(&mut self, branch: usize, patterns: &[Vec<Token>]) {
+        for pattern in patterns {
+            let (prefix, literal) = prefix(pattern);
+            (if literal {
+                &mut self.literals
+            } else {
+                &mut self.globs
+            })
+            .entry(prefix)
+            .or_default()
+            .push(branch);
+            self.seen.entry(pattern.clone()).or_default().push(branch);
+        }
+    }
+
+    /*
+     * Earlier branches that may match the same localparts as patterns, in order.
+     */
+    fn candidates(&self, patterns: &[Vec<Token>]) -> BTreeSet<usize> {
+        let mut candidates = BTreeSet::new();
+        for pattern in patterns {
+            let (prefix, literal) = prefix(pattern);
+            /* Globs starting with a part of the prefix. */
+            for end in prefix
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain(std::iter::once(prefix.len()))
+            {
+                if let Some(branches) = self.globs.get(&prefix[..end]) {
+                    candidates.extend(branches);
+                }
+            }
+            /* The same literal. */
+            if literal {
+                if let Some(branches) = self.literals.get(&prefix) {
+                    candidates.extend(branches);
+                }
+            }
+            /* Anything starting with the whole prefix. */
+            else {
+                for patterns in [&self.literals, &self.globs] {
+                    for (_, branches) in patterns
+                        .range(prefix.clone()..)
+                        .take_while(|(key, _)| key.starts_with(&prefix))
+                    {
+                        candidates.extend(branches);
+                    }
+                }
+            }
+        }
+        candidates
+    }
+}
+
+/*
+ * Chars up to the first wildcard, and whether that's the whole pattern.
+ */
+fn prefix(pattern: &[Token]) -> (String, bool) {
+    let mut prefix = String::new();
+    for token in pattern {
+        match token {
+            Token::Char(c) => prefix.push(*c),
+            _ => return (prefix, false),
+        }
+    }
+    (prefix, true)
+}
+
+/*
+ * RFC 5228 :matches, '*' is any sequence, '?' any one char and '\' escapes the next one.
+ * The default comparator i;ascii-casemap ignores ASCII case.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Token {
+    Char(char),
+    Any,
+    Star,
+}
+
+fn parse(pattern: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            '*' => Token::Star,
+            '?' => Token::Any,
+            '\\' => Token::Char(chars.next().unwrap_or('\\').to_ascii_lowercase()),
+            c => Token::Char(c.to_ascii_lowercase()),
+        });
+    }
+    tokens
+}
+
+/*
+ * Positions reached in every pattern, as (pattern, token) pairs, the
+ * states of the NFA of the patterns' union.
+ */
+type States = BTreeSet<(usize, usize)>;
+
+fn closure(patterns: &[Vec<Token>], mut states: States) -> States {
+    let mut todo = states.iter().copied().collect::<Vec<_>>();
+    while let Some((p, i)) = todo.pop() {
+        if patterns[p].get(i) == Some(&Token::Star) && states.insert((p, i + 1)) {
+            todo.push((p, i + 1));
+        }
+    }
+    states
+}
+
+fn start(patterns: &[Vec<Token>]) -> States {
+    closure(patterns, (0..patterns.len()).map(|p| (p, 0)).collect())
+}
+
+fn step(patterns: &[Vec<Token>], states: &States, c: char) -> States {
+    closure(
+        patterns,
+        states
+            .iter()
+            .filter_map(|&(p, i)| match patterns[p].get(i)? {
+                Token::Char(x) if *x == c => Some((p, i + 1)),
+                Token::Char(_) => None,
+                Token::Any => Some((p, i + 1)),
+                Token::Star => Some((p, i)),
+            })
+            .collect(),
+    )
+}
+
+fn accepts(patterns: &[Vec<Token>], states: &States) -> bool {
+    states.iter().any(|&(p, i)| i == patterns[p].len())
+}
+
+/*
+ * Shortest non-empty localpart for which accept(matched by a, matched by b) holds,
+ * searched breadth first over both NFAs at once. Chars that are not in any pattern
+ * all behave the same, one of them stands for every other char.
+ */
+fn find(a: &[Vec<Token>], b: &[Vec<Token>], accept: impl Fn(bool, bool) -> bool) -> Option<String> {
+    let mut alphabet = a
+        .iter()
+        .chain(b)
+        .flatten()
+        .filter_map(|token| match token {
+            Token::Char(c) => Some(*c),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+    if let Some(other) = "xyzqjkw0123456789".chars().find(|c| !alphabet.contains(c)) {
+        alphabet.insert(other);
+    }
+
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([(start(a), start(b), String::new())]);
+    while let Some((states_a, states_b, word)) = queue.pop_front() {
+        for &c in &alphabet {
+            let next_a = step(a, &states_a, c);
+            if next_a.is_empty() {
+                continue; // a can't match anymore, neither can a && anything.
+            }
+            let next_b = step(b, &states_b, c);
+            let next_word = format!("{}{}", word, c);
+            if accept(accepts(a, &next_a), accepts(b, &next_b)) {
+                return Some(next_word);
+            }
+            if seen.insert((next_a.clone(), next_b.clone())) {
+                queue.push_back((next_a, next_b, next_word));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, parse, shadowed, Branch};
+
+    fn patterns(localparts: &[&str]) -> Vec<Vec<super::Token>> {
+        localparts.iter().map(|l| parse(l)).collect()
+    }
+
+    #[test]
+    fn find_glob() {
+        let news = patterns(&["news*"]);
+        let newsletter = patterns(&["newsletter", "newsletter.*"]);
+        assert_eq!(
+            find(&newsletter, &news, |a, b| a && b).as_deref(),
+            Some("newsletter")
+        );
+        assert_eq!(find(&newsletter, &news, |a, b| a && !b), None);
+        assert_eq!(
+            find(&news, &newsletter, |a, b| a && !b).as_deref(),
+            Some("news")
+        );
+        assert_eq!(
+            find(&patterns(&["a?c"]), &patterns(&["A\\?C"]), |a, b| a && b).as_deref(),
+            Some("a?c")
+        );
+        assert_eq!(
+            find(&patterns(&["a*"]), &patterns(&["*b"]), |a, b| a && !b).as_deref(),
+            Some("a")
+        );
+        assert_eq!(
+            find(&patterns(&["a"]), &patterns(&["b*"]), |a, b| a && b),
+            None
+        );
+    }
+
+    #[test]
+    fn shadowed_branches() {
+        let config_path = vec![];
+        let localparts = [
+            vec!["news*".to_string()],
+            vec!["newsletter".to_string(), "newsletter.*".to_string()],
+            vec!["shop*".to_string(), "bank".to_string()],
+            vec!["shopping".to_string(), "bills".to_string()],
+            vec!["bank".to_string()],
+        ];
+        let paths = ["News", "Newsletter", "Shop", "Shopping", "Bank"];
+        let branches = paths
+            .iter()
+            .zip(&localparts)
+            .map(|(path, localparts)| Branch {
+                path,
+                generator: "Custom",
+                config_path: &config_path,
+                localparts,
+                match_type: Some(super::MatchType::Matches),
+                part: super::Part::Localpart,
+                recipients: Default::default(),
+            })
+            .collect::<Vec<_>>();
+        let result = shadowed(&branches);
+        assert_eq!(result.len(), 2, "{:?}", result);
+        assert_eq!(
+            (result[0].later, &result[0].earlier, result[0].full),
+            (1, &vec![0], true)
+        );
+        assert_eq!(result[0].example, "newsletter");
+        assert_eq!(
+            (result[1].later, &result[1].earlier, result[1].full),
+            (3, &vec![2], false)
+        );
+        assert_eq!(result[1].example, "shopping");
+    }
+}
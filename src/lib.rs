@@ -0,0 +1,486 @@
+/*
+ * Generate Sieve filters from an allowlist of localparts per folder.
+ *
+ * let config = Config::parse(text, Format::Json5)?;
+ * let script = generate(&config, &Options { prefix, ..Options::default() })?;
+ * script.write(&mut out)?;
+ *
+ * The config can also be built in code with Config::new().domain(Domain::new(..)).
+ * The sieve-generator binary is a thin wrapper over this, reading and writing files.
+ */
+
+mod common;
+mod config;
+mod error;
+mod generators;
+pub mod sieve;
+mod source;
+mod target;
+
+pub use crate::{
+    common::{
+        CatchAll, DomainOptions, FilterOptions, FullFilter, MatchType, Part, Recipient, Recipients,
+        SieveDomainConfig, StringOrVec,
+    },
+    config::{Config, Domain, Folder, Format},
+    error::{Error, ErrorKind, GenerateError, Severity},
+    source::{Source, Span},
+    target::Target,
+};
+use generators::DomainGenerator;
+use std::{
+    fmt,
+    io::{self, Write},
+};
+
+/*
+ * What applies to every domain, a domain option takes precedence.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub target: Option<Target>,
+    /* Sieve put before the filters, its require commands are merged with the script's. */
+    pub prefix: String,
+}
+
+/*
+ * The generated filters, with the warnings found along the way.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script {
+    pub commands: Vec<sieve::Command>,
+    pub prefix: String,
+    pub warnings: Vec<GenerateError>,
+}
+
+/*
+ * Generate the sieve code of every domain. Keep going on errors, they are
+ * all returned at the end along with the warnings. Ok only has warnings.
+ */
+pub fn generate(config: &Config, options: &Options) -> Result<Script, Error> {
+    let mut domains = vec![];
+    let mut targets = options.target.into_iter().collect::<Vec<_>>();
+    let mut diagnostics = config.diagnostics.clone();
+    for domain in &config.domains {
+        /* *domain.com would be a literal domain which no address has. */
+        if domain.name.starts_with('*') && !common::is_wildcard(&domain.name) {
+            diagnostics.push(ErrorKind::InvalidWildcardDomain.at::<&str>(&domain.name, &[]));
+            continue;
+        }
+        let mut domain_options = domain.options.clone();
+        domain_options.target = domain_options.target.or(options.target);
+        if let Some(target) = domain_options.target {
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+        let mut g = DomainGenerator::new(&domain.name, &domain_options);
+        match g.generate(domain.config.clone()) {
+            Ok(warnings) => diagnostics.extend(warnings),
+            Err(e) => {
+                diagnostics.extend(e);
+                continue;
+            }
+        }
+        /* The subdomain is kept before the filters' own :matches overwrite ${1}. */
+        let mut commands = vec![];
+        if domain_options.subdomain_as_first_folder {
+            commands.push(sieve::Command::Set(
+                common::SUBDOMAIN.to_string(),
+                "${1}".to_string(),
+            ));
+        }
+        commands.push(g.to_sieve());
+        domains.push((
+            domain_options.priority,
+            sieve::Branch::new(
+                g.profile().recipient(
+                    sieve::AddressPart::Domain,
+                    if common::is_wildcard(&domain.name) {
+                        sieve::MatchType::Matches
+                    } else {
+                        sieve::MatchType::Is
+                    },
+                    domain.name.as_str().into(),
+                    domain_options.recipients,
+                ),
+                commands,
+            )
+            .with_comment(&format!("@{}", domain.name)),
+        ));
+    }
+    /* Stable, domains of the same priority stay in config order. */
+    domains.sort_by_key(|(priority, _)| std::cmp::Reverse(*priority));
+    let mut script = Script {
+        commands: vec![sieve::Command::If(sieve::If::new(
+            domains.into_iter().map(|(_, branch)| branch).collect(),
+            None,
+        ))],
+        prefix: options.prefix.clone(),
+        warnings: vec![],
+    };
+    /* The prefix and the size are the whole script's, every target of a domain applies. */
+    for target in targets {
+        diagnostics.extend(check_target(&script, target));
+    }
+    if diagnostics.iter().any(GenerateError::is_error) {
+        Err(Error { diagnostics })
+    } else {
+        script.warnings = diagnostics;
+        Ok(script)
+    }
+}
+
+/*
+ * What the generators can't see, the prefix and the size of the whole script.
+ */
+fn check_target(script: &Script, target: Target) -> Vec<GenerateError> {
+    let profile = target.profile();
+    let mut errors = sieve::split_requires(&script.prefix)
+        .0
+        .into_iter()
+        .filter(|capability| !profile.supports(capability))
+        .map(|capability| ErrorKind::UnsupportedCapability(capability, target).at("", &["prefix"]))
+        .collect::<Vec<_>>();
+    if let Some(limit) = profile.max_script_size {
+        let mut size = ByteCount(0);
+        script.write(&mut size).unwrap(); // ByteCount never fails.
+        if size.0 > limit {
+            errors.push(ErrorKind::ScriptTooLarge(size.0, limit, target).at("", &["script"]));
+        }
+    }
+    errors
+}
+
+/*
+ * Size of what is written, without keeping it.
+ */
+struct ByteCount(usize);
+
+impl Write for ByteCount {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Script {
+    /*
+     * Prepend the prefix sieve, its require commands are merged with the
+     * capabilities the script uses into one at the top.
+     */
+    pub fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        let (mut capabilities, prefix) = sieve::split_requires(&self.prefix);
+        capabilities.extend(sieve::requires(&self.commands));
+        if !capabilities.is_empty() {
+            out.write_all(
+                sieve::render(&[sieve::Command::Require(
+                    capabilities.into_iter().collect::<Vec<_>>().into(),
+                )])
+                .trim_start()
+                .as_bytes(),
+            )?;
+        }
+        let prefix = prefix.trim();
+        if !prefix.is_empty() {
+            write!(out, "\n{}", prefix)?;
+        }
+        sieve::write(out, &self.commands)
+    }
+}
+
+/*
+ * The whole script, with the prefix.
+ */
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = vec![];
+        self.write(&mut out).map_err(|_| fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&out))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, sieve, Config, Domain, Format, Options, Script, Target};
+
+    fn rendered(script: &Script) -> String {
+        let mut out = vec![];
+        script.write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn generate_example() {
+        let config =
+            Config::parse(include_str!("../examples/sieve.config.json"), Format::Json).unwrap();
+        let script = generate(&config, &Options::default()).unwrap();
+        assert!(script.warnings.is_empty(), "{:?}", script.warnings);
+        assert_eq!(rendered(&script), include_str!("../examples/filter.sieve"));
+        assert_eq!(script.to_string(), include_str!("../examples/filter.sieve"));
+    }
+
+    #[test]
+    fn generate_reproducible() {
+        let config = r#"
+            {
+                "zeta.com": { "Bills": "bills", "Shop": "shop", "Bank": "bank" },
+                "alpha.com": { "Work": "boss" },
+                "mid.com": { "options": { "priority": 1 }, "Home": "mom" }
+            }"#;
+        let generate = || {
+            let config = Config::parse(config, Format::Json).unwrap();
+            rendered(&generate(&config, &Options::default()).unwrap())
+        };
+        let output = generate();
+        assert_eq!(output, generate());
+        let domains = output
+            .lines()
+            .filter(|line| line.starts_with("# @"))
+            .collect::<Vec<_>>();
+        assert_eq!(domains, ["# @mid.com", "# @zeta.com", "# @alpha.com"]);
+    }
+
+    #[test]
+    fn generate_built_config() {
+        let config = Config::new().domain(
+            Domain::new("domain.com")
+                .folder("Newsletter", |f| f.localparts(["wallstreet"]))
+                .folder("Bank", |f| {
+                    f.localparts(["bank"]).generic(false).fullpath(true)
+                }),
+        );
+        let e = generate(&config, &Options::default()).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "error[E0010]: not generating generic filters, set fullpath option is useless\n  --> domain.com › Bank › options › fullpath"
+        );
+
+        let config = Config::parse(
+            r#"{ "domain.com": { "Newsletter": "wallstreet" } }"#,
+            Format::Json,
+        )
+        .unwrap();
+        let parsed = generate(&config, &Options::default()).unwrap();
+        let config = Config::new().domain(
+            Domain::new("domain.com").folder("Newsletter", |f| f.localparts(["wallstreet"])),
+        );
+        assert_eq!(generate(&config, &Options::default()).unwrap(), parsed);
+    }
+
+    #[test]
+    fn generate_recipients() {
+        let config = Config::parse(
+            r#"
+            {
+                "domain.com": {
+                    "options": { "recipients": ["envelope", "x-original-to"] },
+                    "Work": "boss",
+                    "Forwarded": {
+                        "localparts": "boss",
+                        "options": { "generic": false, "recipients": ["delivered-to", "cc"] }
+                    }
+                }
+            }"#,
+            Format::Json,
+        )
+        .unwrap();
+        let script = generate(&config, &Options::default()).unwrap();
+        /*
+         * Same localpart, other recipients, nothing is shadowed. Only a mail that also
+         * passes the domain test gets to Forwarded though.
+         */
+        assert_eq!(
+            script.warnings.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            [
+                "warning[L0008]: \"cc\" is not in the domain's recipients, a mail only sent there never gets to this filter\n  --> domain.com › Forwarded › options › recipients",
+                "warning[L0008]: \"delivered-to\" is not in the domain's recipients, a mail only sent there never gets to this filter\n  --> domain.com › Forwarded › options › recipients",
+            ]
+        );
+        assert_eq!(
+            rendered(&script),
+            r#"require ["envelope","fileinto","imap4flags"];
+# @domain.com
+if anyof(envelope :domain :is "to" "domain.com", address :domain :is "x-original-to" "domain.com") {
+    # Custom filters
+    if anyof(envelope :localpart :matches "to" ["boss"], address :localpart :matches "x-original-to" ["boss"]) {
+        fileinto "Work";
+    } elsif address :localpart :matches ["cc","delivered-to"] ["boss"] {
+        fileinto "Forwarded";
+    }
+    # Generic filters
+    elsif anyof(envelope :localpart :matches "to" ["work","work.*"], address :localpart :matches "x-original-to" ["work","work.*"]) {
+        fileinto "Work";
+    } else {
+        addflag "\\Seen";
+        fileinto "Unknown";
+    }
+}"#
+        );
+
+        /* Without envelope, the domain test is on the to header already. */
+        let config = Config::parse(
+            r#"{ "domain.com": { "Work": { "localparts": "boss", "options": { "recipients": ["to"] } } } }"#,
+            Format::Json,
+        )
+        .unwrap();
+        let warnings = |target| {
+            generate(
+                &config,
+                &Options {
+                    target,
+                    ..Options::default()
+                },
+            )
+            .unwrap()
+            .warnings
+            .iter()
+            .map(|e| e.code())
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(warnings(None), ["L0008"]);
+        assert!(warnings(Some(Target::Proton)).is_empty());
+    }
+
+    #[test]
+    fn generate_wildcard_domain() {
+        let config = Config::parse(
+            r#"
+            {
+                "*.domain.com": {
+                    "options": { "subdomain-as-first-folder": true },
+                    "Bills": "bills"
+                }
+            }"#,
+            Format::Json,
+        )
+        .unwrap();
+        let script = generate(&config, &Options::default()).unwrap();
+        assert_eq!(
+            rendered(&script),
+            r#"require ["envelope","fileinto","imap4flags","variables"];
+# @*.domain.com
+if envelope :domain :matches "to" "*.domain.com" {
+    set "subdomain" "${1}";
+    # Custom filters
+    if envelope :localpart :matches "to" ["bills"] {
+        fileinto "${subdomain}/Bills";
+    }
+    # Generic filters
+    elsif envelope :localpart :matches "to" ["bills","bills.*"] {
+        fileinto "${subdomain}/Bills";
+    } else {
+        addflag "\\Seen";
+        fileinto "Unknown";
+    }
+}"#
+        );
+
+        let e = generate(
+            &Config::parse(
+                r#"{ "domain.com": { "options": { "subdomain-as-first-folder": true }, "Bills": "bills" } }"#,
+                Format::Json,
+            )
+            .unwrap(),
+            &Options::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "error[E0019]: subdomain-as-first-folder option must be false unless the domain is *.domain\n  --> domain.com › options › subdomain-as-first-folder"
+        );
+
+        let script = generate(
+            &Config::parse(
+                r#"{ "*.domain.com": { "options": { "domain-as-first-folder": true, "subdomain-as-first-folder": true }, "Bills": "bills" } }"#,
+                Format::Json,
+            )
+            .unwrap(),
+            &Options::default(),
+        )
+        .unwrap();
+        assert!(rendered(&script).contains(r#"fileinto "@domain.com/${subdomain}/Bills";"#));
+
+        let e = generate(
+            &Config::parse(r#"{ "*domain.com": { "Bills": "bills" } }"#, Format::Json).unwrap(),
+            &Options::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "error[E0022]: a wildcard domain must start with \"*.\", as in *.domain.com\n  --> *domain.com"
+        );
+    }
+
+    #[test]
+    fn write_script() {
+        let script = Script {
+            commands: vec![sieve::Command::FileInto("A".to_string())],
+            prefix: "# My rules\nrequire [\"fileinto\", \"vacation\"];\nrequire \"envelope\";\n\nif true { keep; }\n".to_string(),
+            warnings: vec![],
+        };
+        assert_eq!(
+            rendered(&script),
+            "require [\"envelope\",\"fileinto\",\"vacation\"];\n# My rules\n\nif true { keep; }\nfileinto \"A\";"
+        );
+        assert_eq!(
+            rendered(&Script {
+                commands: vec![],
+                prefix: String::new(),
+                warnings: vec![]
+            }),
+            ""
+        );
+    }
+
+    #[test]
+    fn generate_target_script() {
+        let config =
+            Config::parse(r#"{ "domain.com": { "Work": "boss" } }"#, Format::Json).unwrap();
+        let options = Options {
+            target: Some(Target::Proton),
+            prefix: "require [\"fileinto\", \"vnd.proton.expire\", \"body\"];".to_string(),
+        };
+        assert_eq!(
+            generate(&config, &options).unwrap_err().to_string(),
+            "error[E0020]: Proton does not support the \"body\" extension\n  --> prefix"
+        );
+
+        /* Pigeonhole takes at most 1 MiB, prefix included. */
+        let options = Options {
+            target: Some(Target::Pigeonhole),
+            prefix: format!("# {}", "x".repeat(1024 * 1024)),
+        };
+        let e = generate(&config, &options).unwrap_err();
+        assert_eq!(e.diagnostics.len(), 1);
+        assert_eq!(e.diagnostics[0].code(), "E0021");
+        assert_eq!(e.diagnostics[0].location(), "script");
+        assert!(generate(
+            &config,
+            &Options {
+                target: Some(Target::Pigeonhole),
+                prefix: String::new()
+            }
+        )
+        .is_ok());
+
+        /* The target domain option alone is enough. */
+        let config = Config::parse(
+            r#"{ "domain.com": { "options": { "target": "proton" }, "Work": "boss" } }"#,
+            Format::Json,
+        )
+        .unwrap();
+        let options = Options {
+            target: None,
+            prefix: "require \"body\";".to_string(),
+        };
+        assert_eq!(
+            generate(&config, &options).unwrap_err().to_string(),
+            "error[E0020]: Proton does not support the \"body\" extension\n  --> prefix"
+        );
+    }
+}
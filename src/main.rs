@@ -1,169 +1,196 @@
 #![doc = include_str!("../README.md")]
 
-mod common;
-mod generators;
-
-use crate::{
-    common::{code_block, SieveDomainConfig},
-    generators::DomainGenerator,
+use clap::{Parser, Subcommand};
+use sieve_generator::{
+    generate, Config, ErrorKind, Format, GenerateError, Options, Source, Target,
 };
-use clap::Parser;
 use std::{
-    collections::HashMap,
-    fs::{self, File},
-    io::BufReader,
+    fs,
+    io::{self, Write},
     path::Path,
+    process,
 };
 
 #[derive(Debug, Parser)]
 #[command(author, version, about)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /*
      * Set config file path, default to sieve.config.json
      */
-    #[arg(short, long, value_name = "FILE", default_value = "sieve.config.json")]
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        default_value = "sieve.config.json",
+        global = true
+    )]
     config: String,
 
     /*
      * Set prefix sieve file path, default to prefix.sieve if exists.
      */
-    #[arg(short, long, value_name = "FILE", default_value = "prefix.sieve")]
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        default_value = "prefix.sieve",
+        global = true
+    )]
     prefix: String,
 
     /*
      * Set output file path, default to filter.sieve
      */
-    #[arg(short, long, value_name = "FILE", default_value = "filter.sieve")]
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        default_value = "filter.sieve",
+        global = true
+    )]
     output: String,
+
+    /*
+     * Set config file format, default to guessing from the config file extension.
+     */
+    #[arg(short, long, value_enum, global = true)]
+    format: Option<Format>,
+
+    /*
+     * Sieve server the script is for, what it doesn't support is an error.
+     */
+    #[arg(short, long, value_enum, global = true)]
+    target: Option<Target>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /*
+     * Never read the prefix nor write the output, for pre-commit hooks.
+     */
+    #[command(
+        about = "Validate the config and run the lints, exit with 0 if ok, 1 on warnings, 2 on errors"
+    )]
+    Check,
 }
 
 fn main() {
     let mut args = Args::parse();
+    let check = matches!(args.command, Some(Command::Check));
+    let mut source = match fs::read_to_string(&args.config) {
+        Ok(text) => Source::new(&args.config, text),
+        Err(e) => {
+            report(
+                &[ErrorKind::ReadConfig(format!("{}, {}", args.config, e)).at::<&str>("", &[])],
+                None,
+            );
+            process::exit(if check { 2 } else { 1 });
+        }
+    };
     if Path::new(&args.output).is_dir() {
         args.output = format!("{}/filter.sieve", args.output);
     }
-
-    let mut sieve_code = String::from("");
-    for (i, (domain, sieve_domain_config)) in
-        read_sieve_config_json(&args.config).iter_mut().enumerate()
-    {
-        let (sieve_domain_config, domain_as_first_folder) = prepare(sieve_domain_config);
-        sieve_code = sieve_code
-            + &format!("\n# @{domain}")
-            + if i == 0 { "\nif" } else { " elsif" }
-            + &format!(" envelope :domain :is \"to\" \"{domain}\" {{")
-            + &code_block({
-                let mut g = DomainGenerator::new(&domain, domain_as_first_folder);
-                g.generate(sieve_domain_config);
-                g.to_string()
-            })
-            + "\n}";
+    let format = args
+        .format
+        .unwrap_or_else(|| Format::from_path(&args.config));
+    let options = Options {
+        target: args.target,
+        prefix: if check {
+            String::new()
+        } else {
+            fs::read_to_string(&args.prefix).unwrap_or_default()
+        },
+    };
+    let result =
+        Config::from_source(&mut source, format).and_then(|config| generate(&config, &options));
+    let diagnostics = match &result {
+        Ok(script) => &script.warnings,
+        Err(e) => &e.diagnostics,
+    };
+    report(&located(diagnostics, &args), Some(&source));
+    if check {
+        process::exit(match &result {
+            Ok(script) if script.warnings.is_empty() => 0,
+            Ok(_) => 1,
+            Err(_) => 2,
+        });
     }
-    let content = fs::read_to_string(&args.prefix).unwrap_or_default() + &sieve_code; // Prepend the prefix sieve and write to output
-    fs::write(&args.output, &content).unwrap_or_else(|_| {
-        println!(
-            "WARNING: Write to {} failed, dumpling final content to stdout...\n{}",
-            args.output, content
-        )
+    let script = match result {
+        Ok(script) => script,
+        Err(_) => process::exit(1),
+    };
+
+    let written = fs::File::create(&args.output).and_then(|file| {
+        let mut out = io::BufWriter::new(file);
+        script.write(&mut out)?;
+        out.flush()
     });
+    if let Err(e) = written {
+        println!(
+            "WARNING: Write to {} failed, {}, dumpling final content to stdout...",
+            args.output, e
+        );
+        let mut out = io::BufWriter::new(io::stdout().lock());
+        script
+            .write(&mut out)
+            .and_then(|_| out.flush())
+            .unwrap_or_else(|_| process::exit(1));
+    }
 }
 
 /*
- * Read as type SieveConfig to avoid having to include the options in the type
- * since there's only one and it will be removed from the object anyway.
- *
- * Enforce config json file structure.
- * {
- *     [domain: string]: {
- *          options,
- *         [dirname: string]: JSON
- *    }
- * }
+ * The prefix and the script the library reports about are the files of the
+ * --prefix and --output arguments.
  */
-type SieveConfig = HashMap<String, HashMap<String, serde_json::Value>>;
-fn read_sieve_config_json(file_path: &str) -> SieveConfig {
-    File::open(file_path)
-        .and_then(|file| -> Result<SieveConfig, std::io::Error> {
-            serde_json::from_reader(BufReader::new(file)).map_err(|e| e.into())
+fn located(diagnostics: &[GenerateError], args: &Args) -> Vec<GenerateError> {
+    diagnostics
+        .iter()
+        .cloned()
+        .map(|mut e| {
+            if e.domain.is_empty() {
+                for key in &mut e.path {
+                    match key.as_str() {
+                        "prefix" => *key = args.prefix.clone(),
+                        "script" => *key = args.output.clone(),
+                        _ => {}
+                    }
+                }
+            }
+            e
         })
-        .unwrap()
+        .collect()
 }
 
 /*
- * Take out the options object and return the domain config object.
+ * Print errors and warnings to stderr with a summary.
  */
-fn prepare(
-    sieve_domain_config: &mut HashMap<String, serde_json::Value>,
-) -> (SieveDomainConfig, bool) {
-    let mut domain_as_first_folder = false;
-    if let Some(options) = sieve_domain_config.remove("options") {
-        if let serde_json::Value::Object(mut options) = options {
-            if let Some(value) = options.remove("domain-as-first-folder") {
-                domain_as_first_folder = serde_json::from_value::<bool>(value)
-                    .expect("ERROR: Haizaa... domain-as-first-folder must be bool...");
+fn report(diagnostics: &[GenerateError], source: Option<&Source>) {
+    for e in diagnostics {
+        match source {
+            /* Outside of any domain with a path, it is about the prefix or the output. */
+            Some(source) if !e.domain.is_empty() || e.path.is_empty() => {
+                eprintln!("{}\n", source.render(e))
             }
-        } else {
-            panic!("ERROR: Don't you know domain options must be object ((; ?");
+            _ => eprintln!("{}\n", e),
         }
     }
-    (
-        serde_json::from_value(serde_json::to_value(sieve_domain_config).unwrap()).unwrap(),
-        domain_as_first_folder,
-    )
-}
-
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn prepare() {
-        let (allowlist, domain_as_first_folder) = super::prepare(
-            &mut serde_json::from_str::<super::HashMap<String, serde_json::Value>>(
-                r#"
-                {
-                    "options": {
-                        "domain-as-first-folder": true
-                    },
-                    "Newsletter": {
-                        "Business": "wallstreet"
-                    }
-                }"#,
-            )
-            .unwrap(),
-        );
-        assert_eq!(
-            serde_json::to_string(&allowlist).unwrap(),
-            "{\"Newsletter\":{\"Business\":\"wallstreet\"}}",
-        );
-        assert!(domain_as_first_folder);
-    }
-
-    #[test]
-    #[should_panic(expected = "must be object")]
-    fn prepare_panic_options_not_object() {
-        super::prepare(
-            &mut serde_json::from_str::<super::HashMap<String, serde_json::Value>>(
-                r#"
-                {
-                    "options": ""
-                }"#,
-            )
-            .unwrap(),
-        );
-    }
-
-    #[test]
-    #[should_panic(expected = "expected a boolean")]
-    fn prepare_panic_options_first_folder_not_boolean() {
-        super::prepare(
-            &mut serde_json::from_str::<super::HashMap<String, serde_json::Value>>(
-                r#"
-                {
-                    "options": {
-                        "domain-as-first-folder": ""
-                    }
-                }"#,
-            )
-            .unwrap(),
+    let plural = |n: usize, what: &str| format!("{} {}{}", n, what, if n > 1 { "s" } else { "" });
+    let errors = diagnostics.iter().filter(|e| e.is_error()).count();
+    let warnings = diagnostics.len() - errors;
+    if errors > 0 {
+        eprintln!(
+            "error: aborting due to {}{}",
+            plural(errors, "previous error"),
+            if warnings > 0 {
+                format!("; {} emitted", plural(warnings, "warning"))
+            } else {
+                String::new()
+            }
         );
+    } else if warnings > 0 {
+        eprintln!("warning: {} emitted", plural(warnings, "warning"));
     }
 }
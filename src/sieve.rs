@@ -0,0 +1,612 @@
+/*
+ * Just enough of RFC 5228 (and the extensions in use) to write the filters:
+ * the generators build a tree of commands, render() takes care of the layout,
+ * the string escaping and the line endings.
+ */
+
+use regex::Regex;
+use std::{
+    collections::BTreeSet,
+    io::{self, Write},
+    sync::LazyLock,
+};
+
+const NEWLINE: &str = "\n";
+const INDENT: &str = "    ";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Require(StringList),
+    Comment(String),
+    FileInto(String),
+    FileIntoCreate(String), // fileinto :create, the folder is created if missing.
+    AddFlag(String),
+    Keep,
+    Discard,
+    Reject(String),      // The message.
+    Redirect(String),    // The address.
+    Set(String, String), // set "name" "value", for a match variable to outlive the next match.
+    If(If),
+}
+
+/*
+ * if, then every elsif, then else. Comments are written on their own line
+ * before the if or elsif keyword of their branch.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct If {
+    pub branches: Vec<Branch>,
+    pub otherwise: Option<Vec<Command>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    pub comment: Option<String>,
+    pub test: Test,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Test {
+    Envelope {
+        address_part: AddressPart,
+        match_type: MatchType,
+        envelope_parts: StringList,
+        keys: StringList,
+    },
+    Address {
+        address_part: AddressPart,
+        match_type: MatchType,
+        headers: StringList,
+        keys: StringList,
+    },
+    Header {
+        match_type: MatchType,
+        headers: StringList,
+        keys: StringList,
+    },
+    AllOf(Vec<Test>),
+    AnyOf(Vec<Test>),
+    Not(Box<Test>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddressPart {
+    All,
+    Localpart,
+    Domain,
+    User,   // subaddress
+    Detail, // subaddress
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchType {
+    Is,
+    Contains,
+    Matches,
+    Regex,
+}
+
+/*
+ * A string list is either one string or a bracketed list, kept as built
+ * so "to" isn't written ["to"].
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum StringList {
+    One(String),
+    List(Vec<String>),
+}
+
+impl From<&str> for StringList {
+    fn from(s: &str) -> Self {
+        StringList::One(s.to_string())
+    }
+}
+
+impl From<Vec<String>> for StringList {
+    fn from(list: Vec<String>) -> Self {
+        StringList::List(list)
+    }
+}
+
+impl From<Vec<&str>> for StringList {
+    fn from(list: Vec<&str>) -> Self {
+        StringList::List(list.into_iter().map(String::from).collect())
+    }
+}
+
+impl If {
+    pub fn new(branches: Vec<Branch>, otherwise: Option<Vec<Command>>) -> Self {
+        If {
+            branches,
+            otherwise,
+        }
+    }
+}
+
+impl Branch {
+    pub fn new(test: Test, commands: Vec<Command>) -> Self {
+        Branch {
+            comment: None,
+            test,
+            commands,
+        }
+    }
+
+    pub fn with_comment(mut self, comment: &str) -> Self {
+        self.comment = Some(comment.to_string());
+        self
+    }
+}
+
+/*
+ * Capabilities the commands need, to require them.
+ */
+pub fn requires(commands: &[Command]) -> BTreeSet<String> {
+    let mut capabilities = BTreeSet::new();
+    for command in commands {
+        command_requires(command, &mut capabilities);
+    }
+    capabilities
+}
+
+fn command_requires(command: &Command, capabilities: &mut BTreeSet<String>) {
+    match command {
+        Command::Require(_)
+        | Command::Comment(_)
+        | Command::Keep
+        | Command::Discard
+        | Command::Redirect(_) => {}
+        Command::Reject(_) => {
+            capabilities.insert("reject".to_string());
+        }
+        Command::Set(..) => {
+            capabilities.insert("variables".to_string());
+        }
+        Command::FileInto(folder) | Command::FileIntoCreate(folder) => {
+            capabilities.insert("fileinto".to_string());
+            if let Command::FileIntoCreate(_) = command {
+                capabilities.insert("mailbox".to_string());
+            }
+            /* The generators only write ${ to expand a match variable. */
+            if folder.contains("${") {
+                capabilities.insert("variables".to_string());
+            }
+        }
+        Command::AddFlag(_) => {
+            capabilities.insert("imap4flags".to_string());
+        }
+        Command::If(if_) => {
+            for branch in &if_.branches {
+                test_requires(&branch.test, capabilities);
+                capabilities.extend(requires(&branch.commands));
+            }
+            capabilities.extend(requires(if_.otherwise.as_deref().unwrap_or_default()));
+        }
+    }
+}
+
+fn test_requires(test: &Test, capabilities: &mut BTreeSet<String>) {
+    let match_type = match test {
+        Test::Envelope {
+            address_part,
+            match_type,
+            ..
+        } => {
+            capabilities.insert("envelope".to_string());
+            address_part_requires(address_part, capabilities);
+            Some(match_type)
+        }
+        Test::Address {
+            address_part,
+            match_type,
+            ..
+        } => {
+            address_part_requires(address_part, capabilities);
+            Some(match_type)
+        }
+        Test::Header { match_type, .. } => Some(match_type),
+        Test::AllOf(tests) | Test::AnyOf(tests) => {
+            tests
+                .iter()
+                .for_each(|test| test_requires(test, capabilities));
+            None
+        }
+        Test::Not(test) => {
+            test_requires(test, capabilities);
+            None
+        }
+    };
+    if match_type == Some(&MatchType::Regex) {
+        capabilities.insert("regex".to_string());
+    }
+}
+
+fn address_part_requires(address_part: &AddressPart, capabilities: &mut BTreeSet<String>) {
+    if matches!(address_part, AddressPart::User | AddressPart::Detail) {
+        capabilities.insert("subaddress".to_string());
+    }
+}
+
+/*
+ * Take the require commands out of a hand written script, with the
+ * capabilities they list. Those in comments are left alone.
+ */
+pub fn split_requires(text: &str) -> (BTreeSet<String>, String) {
+    static REQUIRE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r#"(?m)^[ \t]*require\s*(\[[^\]]*\]|"(?:[^"\\]|\\.)*")\s*;[ \t]*\n?"#).unwrap()
+    });
+    static STRING: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r#""((?:[^"\\]|\\.)*)""#).unwrap());
+    let mut capabilities = BTreeSet::new();
+    for require in REQUIRE.captures_iter(text) {
+        for capability in STRING.captures_iter(&require[1]) {
+            capabilities.insert(capability[1].replace("\\\\", "\\").replace("\\\"", "\""));
+        }
+    }
+    (capabilities, REQUIRE.replace_all(text, "").into_owned())
+}
+
+/*
+ * Every command starts on a new line.
+ */
+pub fn render(commands: &[Command]) -> String {
+    let mut out = vec![];
+    write(&mut out, commands).expect("writing to a Vec doesn't fail");
+    String::from_utf8(out).expect("only str are written")
+}
+
+/*
+ * Same as render, streamed into out, which had better be buffered.
+ */
+pub fn write(out: &mut dyn Write, commands: &[Command]) -> io::Result<()> {
+    write_commands(out, commands, 0)
+}
+
+fn write_commands(out: &mut dyn Write, commands: &[Command], depth: usize) -> io::Result<()> {
+    for command in commands {
+        write_command(out, command, depth)?;
+    }
+    Ok(())
+}
+
+fn new_line(out: &mut dyn Write, depth: usize) -> io::Result<()> {
+    out.write_all(NEWLINE.as_bytes())?;
+    for _ in 0..depth {
+        out.write_all(INDENT.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn write_command(out: &mut dyn Write, command: &Command, depth: usize) -> io::Result<()> {
+    match command {
+        Command::Require(capabilities) => {
+            new_line(out, depth)?;
+            out.write_all(b"require ")?;
+            write_string_list(out, capabilities, depth)?;
+            out.write_all(b";")
+        }
+        Command::Comment(comment) => {
+            new_line(out, depth)?;
+            out.write_all(b"# ")?;
+            out.write_all(comment.replace(['\r', '\n'], " ").as_bytes())
+        }
+        Command::FileInto(folder) => {
+            new_line(out, depth)?;
+            out.write_all(b"fileinto ")?;
+            write_string(out, folder, depth)?;
+            out.write_all(b";")
+        }
+        Command::FileIntoCreate(folder) => {
+            new_line(out, depth)?;
+            out.write_all(b"fileinto :create ")?;
+            write_string(out, folder, depth)?;
+            out.write_all(b";")
+        }
+        Command::AddFlag(flag) => {
+            new_line(out, depth)?;
+            out.write_all(b"addflag ")?;
+            write_string(out, flag, depth)?;
+            out.write_all(b";")
+        }
+        Command::Keep => {
+            new_line(out, depth)?;
+            out.write_all(b"keep;")
+        }
+        Command::Discard => {
+            new_line(out, depth)?;
+            out.write_all(b"discard;")
+        }
+        Command::Reject(message) => {
+            new_line(out, depth)?;
+            out.write_all(b"reject ")?;
+            write_string(out, message, depth)?;
+            out.write_all(b";")
+        }
+        Command::Redirect(address) => {
+            new_line(out, depth)?;
+            out.write_all(b"redirect ")?;
+            write_string(out, address, depth)?;
+            out.write_all(b";")
+        }
+        Command::Set(name, value) => {
+            new_line(out, depth)?;
+            out.write_all(b"set ")?;
+            write_string(out, name, depth)?;
+            out.write_all(b" ")?;
+            write_string(out, value, depth)?;
+            out.write_all(b";")
+        }
+        Command::If(if_) => write_if(out, if_, depth),
+    }
+}
+
+/*
+ * } elsif ... {
+ * } else {
+ * An if without any branch is only its else commands.
+ */
+fn write_if(out: &mut dyn Write, if_: &If, depth: usize) -> io::Result<()> {
+    for (i, branch) in if_.branches.iter().enumerate() {
+        if let Some(comment) = &branch.comment {
+            write_command(out, &Command::Comment(comment.clone()), depth)?;
+            new_line(out, depth)?;
+        } else if i == 0 {
+            new_line(out, depth)?;
+        } else {
+            out.write_all(b" ")?;
+        }
+        out.write_all(if i == 0 { b"if " } else { b"elsif " })?;
+        write_test(out, &branch.test, depth)?;
+        out.write_all(b" {")?;
+        write_commands(out, &branch.commands, depth + 1)?;
+        new_line(out, depth)?;
+        out.write_all(b"}")?;
+    }
+    if let Some(otherwise) = &if_.otherwise {
+        if if_.branches.is_empty() {
+            write_commands(out, otherwise, depth)?;
+        } else {
+            out.write_all(b" else {")?;
+            write_commands(out, otherwise, depth + 1)?;
+            new_line(out, depth)?;
+            out.write_all(b"}")?;
+        }
+    }
+    Ok(())
+}
+
+fn write_test(out: &mut dyn Write, test: &Test, depth: usize) -> io::Result<()> {
+    match test {
+        Test::Envelope {
+            address_part,
+            match_type,
+            envelope_parts,
+            keys,
+        } => {
+            out.write_all(b"envelope ")?;
+            out.write_all(address_part.tag().as_bytes())?;
+            out.write_all(match_type.tag().as_bytes())?;
+            write_string_list(out, envelope_parts, depth)?;
+            out.write_all(b" ")?;
+            write_string_list(out, keys, depth)
+        }
+        Test::Address {
+            address_part,
+            match_type,
+            headers,
+            keys,
+        } => {
+            out.write_all(b"address ")?;
+            out.write_all(address_part.tag().as_bytes())?;
+            out.write_all(match_type.tag().as_bytes())?;
+            write_string_list(out, headers, depth)?;
+            out.write_all(b" ")?;
+            write_string_list(out, keys, depth)
+        }
+        Test::Header {
+            match_type,
+            headers,
+            keys,
+        } => {
+            out.write_all(b"header ")?;
+            out.write_all(match_type.tag().as_bytes())?;
+            write_string_list(out, headers, depth)?;
+            out.write_all(b" ")?;
+            write_string_list(out, keys, depth)
+        }
+        Test::AllOf(tests) | Test::AnyOf(tests) => {
+            out.write_all(if matches!(test, Test::AllOf(_)) {
+                b"allof("
+            } else {
+                b"anyof("
+            })?;
+            for (i, test) in tests.iter().enumerate() {
+                if i > 0 {
+                    out.write_all(b", ")?;
+                }
+                write_test(out, test, depth)?;
+            }
+            out.write_all(b")")
+        }
+        Test::Not(test) => {
+            out.write_all(b"not ")?;
+            write_test(out, test, depth)
+        }
+    }
+}
+
+impl AddressPart {
+    /* With its trailing space, :all is the default. */
+    fn tag(&self) -> &'static str {
+        match self {
+            AddressPart::All => "",
+            AddressPart::Localpart => ":localpart ",
+            AddressPart::Domain => ":domain ",
+            AddressPart::User => ":user ",
+            AddressPart::Detail => ":detail ",
+        }
+    }
+}
+
+impl MatchType {
+    /* With its trailing space, :is is the default but always written. */
+    fn tag(&self) -> &'static str {
+        match self {
+            MatchType::Is => ":is ",
+            MatchType::Contains => ":contains ",
+            MatchType::Matches => ":matches ",
+            MatchType::Regex => ":regex ",
+        }
+    }
+}
+
+/*
+ * ["a","b"]
+ */
+fn write_string_list(out: &mut dyn Write, list: &StringList, depth: usize) -> io::Result<()> {
+    match list {
+        StringList::One(s) => write_string(out, s, depth),
+        StringList::List(list) => {
+            out.write_all(b"[")?;
+            for (i, s) in list.iter().enumerate() {
+                if i > 0 {
+                    out.write_all(b",")?;
+                }
+                write_string(out, s, depth)?;
+            }
+            out.write_all(b"]")
+        }
+    }
+}
+
+/*
+ * A quoted string only knows \\ and \", anything else is kept as is, UTF-8
+ * included. A string with a line break has to be a multi-line text: literal,
+ * which isn't indented and in which a line starting with '.' gets another one.
+ * What follows it goes on the next line.
+ */
+fn write_string(out: &mut dyn Write, s: &str, depth: usize) -> io::Result<()> {
+    if s.contains(['\n', '\r']) {
+        out.write_all(b"text:")?;
+        out.write_all(NEWLINE.as_bytes())?;
+        for line in s.lines() {
+            if line.starts_with('.') {
+                out.write_all(b".")?;
+            }
+            out.write_all(line.as_bytes())?;
+            out.write_all(NEWLINE.as_bytes())?;
+        }
+        out.write_all(b".")?;
+        new_line(out, depth)
+    } else {
+        out.write_all(b"\"")?;
+        let bytes = s.as_bytes();
+        let mut start = 0;
+        for (i, byte) in bytes.iter().enumerate() {
+            if matches!(byte, b'\\' | b'"') {
+                out.write_all(&bytes[start..i])?;
+                out.write_all(b"\\")?;
+                start = i;
+            }
+        }
+        out.write_all(&bytes[start..])?;
+        out.write_all(b"\"")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AddressPart, Branch, Command, If, MatchType, StringList, Test};
+
+    fn string(s: &str) -> String {
+        let mut out = vec![];
+        super::write_string(&mut out, s, 1).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn render_string() {
+        assert_eq!(string("Café"), "\"Café\"");
+        assert_eq!(string(r#"a "b" \c"#), r#""a \"b\" \\c""#);
+        assert_eq!(string("\tx"), "\"\tx\"");
+        assert_eq!(
+            string("line 1\r\n.line 2"),
+            "text:\nline 1\n..line 2\n.\n    "
+        );
+    }
+
+    #[test]
+    fn render_string_list() {
+        let mut out = vec![];
+        super::write_string_list(&mut out, &vec!["a*", "é\\"].into(), 0).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), r#"["a*","é\\"]"#);
+    }
+
+    #[test]
+    fn render() {
+        let header = |keys: Vec<&str>| Test::Header {
+            match_type: MatchType::Contains,
+            headers: vec!["from", "subject"].into(),
+            keys: keys.into(),
+        };
+        assert_eq!(
+            super::render(&[
+                Command::Require(vec!["fileinto", "imap4flags"].into()),
+                Command::If(If::new(
+                    vec![
+                        Branch::new(
+                            Test::AllOf(vec![
+                                Test::Address {
+                                    address_part: AddressPart::All,
+                                    match_type: MatchType::Is,
+                                    headers: "from".into(),
+                                    keys: StringList::One("boss@domain.com".into()),
+                                },
+                                Test::Not(Box::new(header(vec!["holiday"]))),
+                            ]),
+                            vec![Command::FileInto("Work".into())]
+                        )
+                        .with_comment("Boss"),
+                        Branch::new(
+                            Test::AnyOf(vec![header(vec!["a"]), header(vec!["b\nc"])]),
+                            vec![Command::AddFlag("\\Seen".into())]
+                        ),
+                        Branch::new(
+                            Test::Envelope {
+                                address_part: AddressPart::Localpart,
+                                match_type: MatchType::Regex,
+                                envelope_parts: "to".into(),
+                                keys: vec!["^x$"].into(),
+                            },
+                            vec![Command::If(If::new(
+                                vec![],
+                                Some(vec![Command::Comment("x".into())])
+                            ))]
+                        )
+                        .with_comment("Regex"),
+                    ],
+                    Some(vec![Command::FileInto("Unknown".into())])
+                )),
+            ]),
+            r#"
+require ["fileinto","imap4flags"];
+# Boss
+if allof(address :is "from" "boss@domain.com", not header :contains ["from","subject"] ["holiday"]) {
+    fileinto "Work";
+} elsif anyof(header :contains ["from","subject"] ["a"], header :contains ["from","subject"] [text:
+b
+c
+.
+]) {
+    addflag "\\Seen";
+}
+# Regex
+elsif envelope :localpart :regex "to" ["^x$"] {
+    # x
+} else {
+    fileinto "Unknown";
+}"#
+        );
+    }
+}
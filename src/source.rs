@@ -0,0 +1,593 @@
+use crate::error::GenerateError;
+use std::collections::HashMap;
+
+/*
+ * Byte range in the config file.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/*
+ * The config file as read, with the span of every key of the config
+ * so that errors can point back at it.
+ */
+pub struct Source {
+    pub name: String,
+    pub text: String,
+    spans: HashMap<Vec<String>, Span>,
+    line_starts: Vec<usize>, // Every diagnostic looks its line up, a config can have tens of thousands.
+    comments: Vec<Span>,     // In order, keys in them are not keys.
+}
+
+impl Source {
+    pub fn new(name: &str, text: String) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Source {
+            name: name.to_string(),
+            comments: comments(&text),
+            text,
+            spans: HashMap::new(),
+            line_starts,
+        }
+    }
+
+    /*
+     * Serde doesn't keep track of where a value comes from in any of the formats,
+     * so keys are searched in the text instead, each one within the value of its
+     * parent key and after its previous sibling. It only needs to know what a key
+     * and the bounds of a value look like, which is close enough in JSON, JSON5,
+     * YAML and TOML.
+     */
+    pub fn index(&mut self, config: &serde_json::Value) {
+        if let serde_json::Value::Object(config) = config {
+            let mut from = 0;
+            for (key, value) in config {
+                if let Some(end) = self.index_value(vec![key.clone()], value, from, self.text.len())
+                {
+                    from = end;
+                }
+            }
+        }
+    }
+
+    /*
+     * Index the key at the end of path and what is under it, looked for in
+     * from..to. Return where the next sibling is looked for from.
+     */
+    fn index_value(
+        &mut self,
+        path: Vec<String>,
+        value: &serde_json::Value,
+        from: usize,
+        to: usize,
+    ) -> Option<usize> {
+        let span = find_key(&self.text[..to], &self.comments, from, path.last().unwrap())?;
+        let value_end = value_end(&self.text[..to], span.end);
+        if let serde_json::Value::Object(object) = value {
+            let to = value_end.unwrap_or(to);
+            let mut from = span.end;
+            for (key, value) in object {
+                let mut sub_path = path.clone();
+                sub_path.push(key.clone());
+                if let Some(end) = self.index_value(sub_path, value, from, to) {
+                    from = end;
+                }
+            }
+        }
+        self.spans.insert(path, span);
+        Some(value_end.unwrap_or(span.end))
+    }
+
+    /*
+     * Span of the deepest key of path that is found.
+     */
+    pub fn span_of(&self, path: &[String]) -> Option<Span> {
+        (1..=path.len())
+            .rev()
+            .find_map(|len| self.spans.get(&path[..len]).copied())
+    }
+
+    /*
+     * 1-based, column counted in chars.
+     */
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        (
+            line,
+            self.text[self.line_starts[line - 1]..offset]
+                .chars()
+                .count()
+                + 1,
+        )
+    }
+
+    /*
+     * Rustc style:
+     * error[E0009]: 'self' field is useless if there is no sub-folder
+     *  --> sieve.config.json:4:13
+     *   |
+     * 4 |             "self": "x"
+     *   |             ^^^^^^
+     *   = note: in domain.com › A › self
+     */
+    pub fn render(&self, error: &GenerateError) -> String {
+        let path = std::iter::once(error.domain.clone())
+            .chain(error.path.iter().cloned())
+            .collect::<Vec<_>>();
+        let span = match error.span.or_else(|| self.span_of(&path)) {
+            Some(span) => span,
+            None => return format!("{}\n  --> {}", error, self.name),
+        };
+        let (line, column) = self.line_col(span.start);
+        let line_start = self.text[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_text = self.text[line_start..]
+            .split('\n')
+            .next()
+            .unwrap_or("")
+            .trim_end_matches('\r');
+        let underline_end = span.end.clamp(span.start, line_start + line_text.len());
+        let pad = " ".repeat(line.to_string().len());
+        let mut rendered = format!(
+            "{}[{}]: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+            error.severity,
+            error.code(),
+            error.kind,
+            pad,
+            self.name,
+            line,
+            column,
+            pad,
+            line,
+            line_text,
+            pad,
+            /* Keep the tabs so the underline lines up. */
+            self.text[line_start..span.start]
+                .chars()
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect::<String>(),
+            "^".repeat(self.text[span.start..underline_end].chars().count().max(1)),
+        );
+        let location = error.location();
+        if !location.is_empty() {
+            rendered += &format!("\n{} = note: in {}", pad, location);
+        }
+        rendered
+    }
+}
+
+/*
+ * Inverse of Source::line_col, clamped to the text.
+ */
+pub fn offset_of(text: &str, line: usize, column: usize) -> usize {
+    let line_start = text
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum::<usize>();
+    let line_text = text[line_start..].split('\n').next().unwrap_or("");
+    line_start
+        + line_text
+            .char_indices()
+            .nth(column.saturating_sub(1))
+            .map_or(line_text.len(), |(i, _)| i)
+}
+
+/*
+ * A key is the exact text, quoted or not and out of the comments, followed
+ * by what separates a key from its value (: in JSON and YAML, = in TOML) or
+ * by the rest of a dotted key or a TOML table header.
+ */
+fn find_key(text: &str, comments: &[Span], from: usize, key: &str) -> Option<Span> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    let is_quote = |c: Option<char>| matches!(c, Some('"') | Some('\''));
+    let mut pos = from;
+    while pos <= text.len() {
+        let start = pos + text[pos..].find(key)?;
+        let end = start + key.len();
+        pos = end.max(start + text[start..].chars().next().map_or(1, char::len_utf8));
+        let comment = &comments[comments.partition_point(|comment| comment.end <= start)..];
+        if let Some(comment) = comment.first().filter(|comment| comment.start <= start) {
+            pos = pos.max(comment.end);
+            continue;
+        }
+
+        let before = text[..start].chars().next_back();
+        let after = text[end..].chars().next();
+        let (start, end) = if is_quote(before) && before == after {
+            (start - 1, end + 1)
+        } else if !key.is_empty()
+            && !is_quote(before)
+            && !is_quote(after)
+            && !before.is_some_and(is_word)
+            && !after.is_some_and(is_word)
+        {
+            (start, end)
+        } else {
+            continue;
+        };
+        let previous = text[..start]
+            .trim_end_matches([' ', '\t'])
+            .chars()
+            .next_back();
+        match text[end..].trim_start_matches([' ', '\t']).chars().next() {
+            Some(':') | Some('=') | Some('.') => return Some(Span { start, end }),
+            Some(']') if matches!(previous, Some('.') | Some('[')) => {
+                return Some(Span { start, end })
+            }
+            _ => continue,
+        }
+    }
+    None
+}
+
+/*
+ * End of the value of the key ending at key_end, None if it can't be told,
+ * e.g. a scalar or a TOML table whose keys are spread over several headers.
+ * A bracketed value ends at its closing bracket, a YAML block value at the
+ * first line that is not indented more than the key.
+ */
+fn value_end(text: &str, key_end: usize) -> Option<usize> {
+    let rest = text[key_end..].trim_start_matches([' ', '\t']);
+    let separator = rest.chars().next()?;
+    if separator != ':' && separator != '=' {
+        return None;
+    }
+    let value = rest[1..].trim_start_matches([' ', '\t']);
+    let value_start = text.len() - value.len();
+    match value.chars().next() {
+        Some('{') | Some('[') => closing_bracket(text, value_start),
+        Some('\n') | Some('\r') | Some('#') if separator == ':' => {
+            let line_start = text[..key_end].rfind('\n').map_or(0, |i| i + 1);
+            let indent = indent_of(&text[line_start..]);
+            let mut pos = text[key_end..]
+                .find('\n')
+                .map_or(text.len(), |i| key_end + i + 1);
+            while pos < text.len() {
+                let line = text[pos..].split('\n').next().unwrap_or("");
+                let content = line.trim_start();
+                let blank = content.is_empty() || content.starts_with('#');
+                if !blank && indent_of(line) <= indent && !content.starts_with("- ") {
+                    break;
+                }
+                pos += line.len() + 1;
+            }
+            Some(pos.min(text.len()))
+        }
+        _ => None,
+    }
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start_matches([' ', '\t']).len()
+}
+
+/*
+ * // and /* */ comments of JSON5, # comments of YAML and TOML. A quote only
+ * starts a string out of a word and a string ends with its line at the latest,
+ * YAML's plain scalars can have a lone ' as in don't.
+ */
+fn comments(text: &str) -> Vec<Span> {
+    let mut comments = vec![];
+    let mut chars = text.char_indices().peekable();
+    let mut previous = ' ';
+    let line_end = |i: usize| text[i..].find('\n').map_or(text.len(), |end| i + end);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' | '\'' if !(previous.is_alphanumeric() || previous == '_') => {
+                while let Some(&(_, next)) = chars.peek() {
+                    if next == '\n' {
+                        break;
+                    }
+                    chars.next();
+                    if next == '\\' {
+                        chars.next();
+                    } else if next == c {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek().map(|(_, c)| *c) == Some('*') => {
+                let end = text[i + 2..]
+                    .find("*/")
+                    .map_or(text.len(), |end| i + 2 + end + 2);
+                comments.push(Span { start: i, end });
+                while chars.peek().is_some_and(|(next, _)| *next < end) {
+                    chars.next();
+                }
+            }
+            '/' if chars.peek().map(|(_, c)| *c) == Some('/') => {
+                comments.push(Span {
+                    start: i,
+                    end: line_end(i),
+                });
+                chars.find(|(_, c)| *c == '\n');
+            }
+            '#' if previous.is_whitespace() => {
+                comments.push(Span {
+                    start: i,
+                    end: line_end(i),
+                });
+                chars.find(|(_, c)| *c == '\n');
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+    comments
+}
+
+/*
+ * Just after the bracket closing the one at start, strings and comments skipped.
+ */
+fn closing_bracket(text: &str, start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut chars = text[start..].char_indices().peekable();
+    let mut previous = ' ';
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '{' | '[' => depth += 1,
+            '}' | ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(start + i + 1);
+                }
+            }
+            '"' | '\'' => {
+                while let Some((_, next)) = chars.next() {
+                    if next == '\\' {
+                        chars.next();
+                    } else if next == c {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek().map(|(_, c)| *c) == Some('/') => {
+                chars.find(|(_, c)| *c == '\n');
+            }
+            '/' if chars.peek().map(|(_, c)| *c) == Some('*') => {
+                chars.next();
+                let mut star = false;
+                for (_, next) in chars.by_ref() {
+                    if star && next == '/' {
+                        break;
+                    }
+                    star = next == '*';
+                }
+            }
+            '#' if previous.is_whitespace() => {
+                chars.find(|(_, c)| *c == '\n');
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Source, Span};
+    use crate::error::ErrorKind;
+
+    fn source(text: &str) -> Source {
+        let mut source = Source::new("sieve.config", text.to_string());
+        source.index(
+            &json5::from_str::<serde_json::Value>(text)
+                .or_else(|_| toml::from_str::<serde_json::Value>(text))
+                .or_else(|_| serde_yaml::from_str::<serde_json::Value>(text))
+                .unwrap(),
+        );
+        source
+    }
+
+    fn key_at(source: &Source, path: &[&str]) -> (usize, usize, String) {
+        let span = source
+            .span_of(&path.iter().map(|s| s.to_string()).collect::<Vec<_>>())
+            .unwrap();
+        let (line, column) = source.line_col(span.start);
+        (line, column, source.text[span.start..span.end].to_string())
+    }
+
+    #[test]
+    fn index_json() {
+        let source = source(
+            r#"{
+    // "Utilities": is a comment
+    "domain.com": {
+        "Newsletter": { "Business": "Utilities" },
+        "Utilities": {
+            "self": ["electricity"],
+            "Grocery": { "localparts": "self", "labels": { "label": "keyword" } },
+        },
+    }
+}"#,
+        );
+        assert_eq!(
+            key_at(
+                &source,
+                &["domain.com", "Utilities", "Grocery", "labels", "label"]
+            ),
+            (7, 60, "\"label\"".to_string())
+        );
+        assert_eq!(
+            key_at(&source, &["domain.com", "Utilities", "self"]),
+            (6, 13, "\"self\"".to_string())
+        );
+        /* Missing keys fall back to the parent. */
+        assert_eq!(
+            key_at(
+                &source,
+                &["domain.com", "Newsletter", "Business", "localparts"]
+            ),
+            (4, 25, "\"Business\"".to_string())
+        );
+    }
+
+    #[test]
+    fn index_commented_out_keys() {
+        let json5 = source(
+            r#"{
+    "domain.com": {
+        "Home": "mom", // "Work": used to be here
+        /* "Work": [
+            "boss"
+        ], */
+        "Work": [],
+    }
+}"#,
+        );
+        assert_eq!(
+            key_at(&json5, &["domain.com", "Work"]),
+            (7, 9, "\"Work\"".to_string())
+        );
+        let yaml = source(
+            r#"domain.com:
+  Home: mom # Work: used to be here
+  # Work: boss
+  Work: []
+"#,
+        );
+        assert_eq!(
+            key_at(&yaml, &["domain.com", "Work"]),
+            (4, 3, "Work".to_string())
+        );
+    }
+
+    #[test]
+    fn index_repeated_keys() {
+        let json = source(
+            r#"{
+    "domain.com": {
+        "A": {
+            "B": { "C": "c", "options": { "generic": false } },
+            "C": { "localparts": "", "options": { "generic": false } }
+        },
+        "options": {}
+    }
+}"#,
+        );
+        assert_eq!(
+            key_at(&json, &["domain.com", "A", "C"]),
+            (5, 13, "\"C\"".to_string())
+        );
+        assert_eq!(
+            key_at(&json, &["domain.com", "A", "C", "options", "generic"]),
+            (5, 51, "\"generic\"".to_string())
+        );
+        assert_eq!(
+            key_at(&json, &["domain.com", "options"]),
+            (7, 9, "\"options\"".to_string())
+        );
+        /* Not under A, falls back to A rather than the one in B. */
+        assert_eq!(
+            key_at(&json, &["domain.com", "A", "localparts"]),
+            (3, 9, "\"A\"".to_string())
+        );
+
+        let yaml = source(
+            r#"
+domain.com:
+  A:
+    B:
+      C: c # C:
+    C:
+      localparts: ""
+  C: [x]
+"#,
+        );
+        assert_eq!(
+            key_at(&yaml, &["domain.com", "A", "C"]),
+            (6, 5, "C".to_string())
+        );
+        assert_eq!(key_at(&yaml, &["domain.com", "C"]), (8, 3, "C".to_string()));
+    }
+
+    #[test]
+    fn index_yaml() {
+        let source = source(
+            r#"
+domain.com:
+  Newsletter:
+    Software: [google, facebook]
+  Utilities:
+    Software: electricity
+"#,
+        );
+        assert_eq!(
+            key_at(&source, &["domain.com", "Utilities", "Software"]),
+            (6, 5, "Software".to_string())
+        );
+    }
+
+    #[test]
+    fn index_toml() {
+        let source = source(
+            r#"
+["domain.com".options]
+domain-as-first-folder = false
+
+["domain.com".Utilities.Grocery]
+localparts = "market"
+labels = { label = "keyword" }
+"#,
+        );
+        assert_eq!(
+            key_at(
+                &source,
+                &["domain.com", "Utilities", "Grocery", "labels", "label"]
+            ),
+            (7, 12, "label".to_string())
+        );
+        assert_eq!(
+            key_at(&source, &["domain.com", "Utilities"]),
+            (5, 15, "Utilities".to_string())
+        );
+    }
+
+    #[test]
+    fn offset_of() {
+        let source = Source::new("sieve.config", "ab\ncdé\nf".to_string());
+        assert_eq!(super::offset_of(&source.text, 2, 3), 5);
+        assert_eq!(source.line_col(5), (2, 3));
+        assert_eq!(super::offset_of(&source.text, 3, 1), 8);
+        assert_eq!(super::offset_of(&source.text, 9, 9), 9);
+    }
+
+    #[test]
+    fn render() {
+        let source = source(
+            r#"{
+    "domain.com": {
+        "Utilities": {
+            "self": "electricity"
+        }
+    }
+}"#,
+        );
+        assert_eq!(
+            source
+                .render(&ErrorKind::SelfWithoutSubFolder.at("domain.com", &["Utilities", "self"])),
+            r#"error[E0009]: 'self' field is useless if there is no sub-folder
+ --> sieve.config:4:13
+  |
+4 |             "self": "electricity"
+  |             ^^^^^^
+  = note: in domain.com › Utilities › self"#
+        );
+    }
+
+    #[test]
+    fn render_span() {
+        let mut error = ErrorKind::ParseConfig("invalid JSON".to_string()).at::<&str>("", &[]);
+        error.span = Some(Span { start: 10, end: 11 });
+        assert_eq!(
+            Source::new("sieve.config", "{\n\t\"a\": 1,,\n}".to_string()).render(&error),
+            "error[E0002]: cannot parse config, invalid JSON\n --> sieve.config:2:9\n  |\n2 | \t\"a\": 1,,\n  | \t       ^"
+        );
+    }
+}
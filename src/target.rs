@@ -0,0 +1,284 @@
+use crate::{
+    common::{Recipient, Recipients},
+    sieve::{AddressPart, MatchType, StringList, Test},
+};
+use clap::ValueEnum;
+use std::fmt;
+
+/*
+ * Sieve server the script is written for, set with --target or the
+ * target domain option. Without one nothing is checked.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum Target {
+    /*
+     * Dovecot
+     */
+    Pigeonhole,
+    Proton,
+    Fastmail,
+    Stalwart,
+}
+
+/*
+ * What a target does differently.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Profile {
+    pub separator: char, // Mailbox hierarchy separator.
+    pub envelope: bool,  // Without it the To header is tested instead.
+    /* None is anything. */
+    pub capabilities: Option<&'static [&'static str]>,
+    /* In bytes, None if there's no known limit. */
+    pub max_script_size: Option<usize>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            separator: '/',
+            envelope: true,
+            capabilities: None,
+            max_script_size: None,
+        }
+    }
+}
+
+impl Target {
+    pub fn profile(&self) -> Profile {
+        match self {
+            Target::Pigeonhole => Profile {
+                capabilities: Some(&[
+                    "body",
+                    "comparator-i;ascii-numeric",
+                    "copy",
+                    "date",
+                    "editheader",
+                    "encoded-character",
+                    "envelope",
+                    "environment",
+                    "fileinto",
+                    "imap4flags",
+                    "include",
+                    "index",
+                    "mailbox",
+                    "mime",
+                    "regex",
+                    "reject",
+                    "relational",
+                    "spamtest",
+                    "subaddress",
+                    "vacation",
+                    "variables",
+                ]),
+                max_script_size: Some(1024 * 1024), // sieve_max_script_size default.
+                ..Profile::default()
+            },
+            Target::Proton => Profile {
+                envelope: false,
+                capabilities: Some(&[
+                    "comparator-i;ascii-numeric",
+                    "date",
+                    "environment",
+                    "fileinto",
+                    "imap4flags",
+                    "include",
+                    "relational",
+                    "spamtest",
+                    "vacation",
+                    "variables",
+                    "vnd.proton.expire",
+                ]),
+                ..Profile::default()
+            },
+            Target::Fastmail => Profile {
+                separator: '.',
+                capabilities: Some(&[
+                    "body",
+                    "copy",
+                    "date",
+                    "envelope",
+                    "fileinto",
+                    "imap4flags",
+                    "mailbox",
+                    "regex",
+                    "reject",
+                    "relational",
+                    "subaddress",
+                    "vacation",
+                    "variables",
+                ]),
+                ..Profile::default()
+            },
+            Target::Stalwart => Profile {
+                capabilities: Some(&[
+                    "body",
+                    "comparator-i;ascii-numeric",
+                    "copy",
+                    "date",
+                    "editheader",
+                    "encoded-character",
+                    "envelope",
+                    "environment",
+                    "fileinto",
+                    "imap4flags",
+                    "include",
+                    "index",
+                    "mailbox",
+                    "mime",
+                    "regex",
+                    "reject",
+                    "relational",
+                    "spamtest",
+                    "subaddress",
+                    "vacation",
+                    "variables",
+                ]),
+                ..Profile::default()
+            },
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Target::Pigeonhole => "Pigeonhole",
+            Target::Proton => "Proton",
+            Target::Fastmail => "Fastmail",
+            Target::Stalwart => "Stalwart",
+        })
+    }
+}
+
+impl Profile {
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities
+            .is_none_or(|capabilities| capabilities.contains(&capability))
+    }
+
+    /*
+     * A/B -> A.B if the separator is '.'
+     */
+    pub fn folder(&self, path: &str) -> String {
+        path.replace('/', &self.separator.to_string())
+    }
+
+    /*
+     * Test of the recipient address, envelope :localpart :is "to" "x"
+     * or address :localpart :is "to" "x" if envelope isn't there. The
+     * headers are tested at once, anyof the envelope and them if both.
+     */
+    pub fn recipient(
+        &self,
+        address_part: AddressPart,
+        match_type: MatchType,
+        keys: StringList,
+        recipients: Recipients,
+    ) -> Test {
+        let mut tests = vec![];
+        let mut headers = vec![];
+        for recipient in recipients.iter() {
+            if recipient == Recipient::Envelope && self.envelope {
+                tests.push(Test::Envelope {
+                    address_part,
+                    match_type,
+                    envelope_parts: "to".into(),
+                    keys: keys.clone(),
+                });
+            } else if !headers.contains(&recipient.header()) {
+                headers.push(recipient.header());
+            }
+        }
+        if !headers.is_empty() {
+            tests.push(Test::Address {
+                address_part,
+                match_type,
+                headers: if headers.len() == 1 {
+                    headers[0].into()
+                } else {
+                    headers.into()
+                },
+                keys,
+            });
+        }
+        if tests.len() == 1 {
+            tests.remove(0)
+        } else {
+            Test::AnyOf(tests)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Profile, Target};
+    use crate::{
+        common::{Recipient, Recipients},
+        sieve::{self, AddressPart, MatchType},
+    };
+
+    #[test]
+    fn profile() {
+        let proton = Target::Proton.profile();
+        assert!(proton.supports("vnd.proton.expire"));
+        assert!(!proton.supports("regex"));
+        assert!(Profile::default().supports("regex"));
+        assert_eq!(Target::Fastmail.profile().folder("A/B"), "A.B");
+        assert_eq!(
+            sieve::render(&[sieve::Command::If(sieve::If::new(
+                vec![sieve::Branch::new(
+                    proton.recipient(
+                        AddressPart::Localpart,
+                        MatchType::Is,
+                        "x".into(),
+                        Recipients::default()
+                    ),
+                    vec![]
+                )],
+                None
+            ))]),
+            "\nif address :localpart :is \"to\" \"x\" {\n}"
+        );
+        let recipients = [
+            Recipient::Cc,
+            Recipient::Envelope,
+            Recipient::DeliveredTo,
+            Recipient::To,
+        ]
+        .iter()
+        .copied()
+        .collect::<Recipients>();
+        assert_eq!(
+            sieve::render(&[sieve::Command::If(sieve::If::new(
+                vec![sieve::Branch::new(
+                    Profile::default().recipient(
+                        AddressPart::Domain,
+                        MatchType::Is,
+                        "domain.com".into(),
+                        recipients
+                    ),
+                    vec![]
+                )],
+                None
+            ))]),
+            "\nif anyof(envelope :domain :is \"to\" \"domain.com\", address :domain :is [\"to\",\"cc\",\"delivered-to\"] \"domain.com\") {\n}"
+        );
+        /* Without envelope, envelope and to are the same header. */
+        assert_eq!(
+            sieve::render(&[sieve::Command::If(sieve::If::new(
+                vec![sieve::Branch::new(
+                    proton.recipient(
+                        AddressPart::Domain,
+                        MatchType::Is,
+                        "domain.com".into(),
+                        recipients
+                    ),
+                    vec![]
+                )],
+                None
+            ))]),
+            "\nif address :domain :is [\"to\",\"cc\",\"delivered-to\"] \"domain.com\" {\n}"
+        );
+    }
+}